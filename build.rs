@@ -17,6 +17,14 @@ const FRAGMENT_CODE: &str = "\
 void main() {}\
 ";
 
+const COMPUTE_CODE: &str = "\
+#version 450
+\
+layout(local_size_x = 8, local_size_y = 8) in;
+\
+void main() {}\
+";
+
 fn compile(code: &str, name: &str, kind: shaderc::ShaderKind) -> shaderc::CompilationArtifact {
     let mut options = shaderc::CompileOptions::new().unwrap();
     options.set_optimization_level(shaderc::OptimizationLevel::Performance);
@@ -28,14 +36,185 @@ fn compile(code: &str, name: &str, kind: shaderc::ShaderKind) -> shaderc::Compil
     res
 }
 
+// How a Rust struct shows up in the shaders: a plain struct for storage buffers or a std140
+// uniform block with its instance name, set and binding.
+enum Declaration {
+    Struct,
+    Block(&'static str, u32, u32),
+}
+
+const DECLARATIONS: &[(&str, &str, &str, Declaration)] = &[
+    ("src/octree.rs", "Node", "Node", Declaration::Struct),
+    ("src/dag.rs", "DagNode", "DagNode", Declaration::Struct),
+    ("src/uniforms.rs", "Uniforms", "Uniforms", Declaration::Block("uniforms", 0, 0)),
+    ("src/uniforms.rs", "FrameConstants", "Frame", Declaration::Block("frame", 0, 1)),
+];
+
+// A member of a shared struct, `len` is set for arrays.
+struct Field {
+    name: String,
+    glsl_type: &'static str,
+    len: Option<usize>,
+}
+
+fn glsl_type(rust_type: &str, struct_name: &str) -> &'static str {
+    match rust_type {
+        "i32" => "int",
+        "u32" => "uint",
+        "f32" => "float",
+        "mint::Vector2<f32>" => "vec2",
+        "mint::Vector3<f32>" => "vec3",
+        "mint::Vector4<f32>" => "vec4",
+        _ => panic!("field type {} of {} has no GLSL equivalent", rust_type, struct_name),
+    }
+}
+
+fn strip_visibility(line: &str) -> &str {
+    line.trim_start_matches("pub(crate) ").trim_start_matches("pub ")
+}
+
+// Reads the fields of `struct_name` from the Rust source. Only what the shared structs use is
+// understood: one `name: type,` per line, comments on lines of their own, scalar, vector and
+// array types. Anything else fails the build instead of generating a different layout.
+fn parse_fields(source: &str, struct_name: &str) -> Vec<Field> {
+    let header = format!("struct {} {{", struct_name);
+    let mut lines = source.lines().map(str::trim);
+    lines
+        .by_ref()
+        .find(|line| strip_visibility(line) == header)
+        .unwrap_or_else(|| panic!("struct {} not found", struct_name));
+    let mut fields = vec![];
+    for line in lines {
+        if line == "}" {
+            if fields.is_empty() {
+                panic!("struct {} has no fields", struct_name);
+            }
+            return fields;
+        }
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        if line.starts_with('#') {
+            panic!("attribute {} on a field of {} is not supported", line, struct_name);
+        }
+        let field = line
+            .strip_suffix(',')
+            .unwrap_or_else(|| panic!("field {} of {} has to be on one line ending in a comma", line, struct_name));
+        let (name, rust_type) = strip_visibility(field)
+            .split_once(": ")
+            .unwrap_or_else(|| panic!("can not parse field {} of {}", line, struct_name));
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            panic!("can not parse field {} of {}", line, struct_name);
+        }
+        let (glsl_type, len) = match rust_type.strip_prefix('[').and_then(|array| array.strip_suffix(']')) {
+            Some(array) => {
+                let (element, len) = array
+                    .split_once("; ")
+                    .unwrap_or_else(|| panic!("can not parse array type {} of {}", rust_type, struct_name));
+                let len = len
+                    .parse()
+                    .unwrap_or_else(|_| panic!("array length {} in {} is not a number", len, struct_name));
+                (glsl_type(element, struct_name), Some(len))
+            }
+            None => (glsl_type(rust_type, struct_name), None),
+        };
+        fields.push(Field { name: name.to_string(), glsl_type, len });
+    }
+    panic!("struct {} is not closed", struct_name);
+}
+
+// Size of the GLSL struct or block in std430 or std140, where arrays and structs are aligned
+// to 16 bytes.
+fn glsl_size(fields: &[Field], std140: bool) -> usize {
+    let round_up = |value: usize, align: usize| (value + align - 1) & !(align - 1);
+    let mut offset = 0;
+    let mut struct_align = if std140 { 16 } else { 4 };
+    for field in fields {
+        let (size, align) = match field.glsl_type {
+            "vec2" => (8, 8),
+            "vec3" => (12, 16),
+            "vec4" => (16, 16),
+            _ => (4, 4),
+        };
+        let (size, align) = match field.len {
+            Some(len) => {
+                let align = if std140 { align.max(16) } else { align };
+                (round_up(size, align) * len, align)
+            }
+            None => (size, align),
+        };
+        offset = round_up(offset, align) + size;
+        struct_align = struct_align.max(align);
+    }
+    round_up(offset, struct_align)
+}
+
+// Generates layout.glsl from the Rust structs the shaders read, so both sides always agree on
+// the memory layout. Shaders pull it in with #include "layout.glsl". The sizes are checked
+// against the Rust types in layout_check.rs, which the library includes.
+fn generate_layout(out_dir: &Path) {
+    let mut glsl = String::from("// generated by build.rs from the Rust definitions\n");
+    let mut checks = String::from("// generated by build.rs, the Rust structs have the size of their GLSL declarations\n");
+    let mut block_checks = String::new();
+    for (file, struct_name, glsl_name, declaration) in DECLARATIONS {
+        let source = fs::read_to_string(file).unwrap();
+        let fields = parse_fields(&source, struct_name);
+        let rust_path = format!("crate::{}::{}", file.trim_start_matches("src/").trim_end_matches(".rs"), struct_name);
+        glsl.push('\n');
+        match declaration {
+            Declaration::Struct => {
+                glsl.push_str(&format!("struct {} {{\n", glsl_name));
+                checks.push_str(&format!(
+                    "const _: () = assert!(std::mem::size_of::<{}>() == {}, \"{} differs from its std430 declaration\");\n",
+                    rust_path,
+                    glsl_size(&fields, false),
+                    struct_name
+                ));
+            }
+            Declaration::Block(_, set, binding) => {
+                glsl.push_str(&format!(
+                    "layout(std140, set = {}, binding = {}) uniform {} {{\n",
+                    set, binding, glsl_name
+                ));
+                block_checks.push_str(&format!(
+                    "        assert_eq!(<{} as AsStd140>::std140_size_static(), {}, \"{}\");\n",
+                    rust_path,
+                    glsl_size(&fields, true),
+                    struct_name
+                ));
+            }
+        }
+        for field in fields {
+            match field.len {
+                Some(len) => glsl.push_str(&format!("    {} {}[{}];\n", field.glsl_type, field.name, len)),
+                None => glsl.push_str(&format!("    {} {};\n", field.glsl_type, field.name)),
+            }
+        }
+        match declaration {
+            Declaration::Struct => glsl.push_str("};\n"),
+            Declaration::Block(instance, ..) => glsl.push_str(&format!("}} {};\n", instance)),
+        }
+        println!("cargo:rerun-if-changed={}", file);
+    }
+    checks.push_str(&format!(
+        "\n#[cfg(test)]\nmod layout_check {{\n    use crevice::std140::AsStd140;\n\n    #[test]\n    fn std140_blocks_match_their_declarations() {{\n{}    }}\n}}\n",
+        block_checks
+    ));
+    fs::write(out_dir.join("layout.glsl"), glsl).unwrap();
+    fs::write(out_dir.join("layout_check.rs"), checks).unwrap();
+}
 
 fn main() {
     let out_dir = env::var_os("OUT_DIR").unwrap();
     let vertex_path = Path::new(&out_dir).join("fallback_vert.spv");
     let fragment_path = Path::new(&out_dir).join("fallback_frag.spv");
+    let compute_path = Path::new(&out_dir).join("fallback_comp.spv");
     let vertex_shader = compile(VERTEX_CODE, "fallback_shader.vert", shaderc::ShaderKind::Vertex);
     let fragment_shader = compile(FRAGMENT_CODE, "fallback_shader.frag", shaderc::ShaderKind::Fragment);
+    let compute_shader = compile(COMPUTE_CODE, "fallback_shader.comp", shaderc::ShaderKind::Compute);
     fs::write(&vertex_path, vertex_shader.as_binary_u8()).unwrap();
     fs::write(&fragment_path, fragment_shader.as_binary_u8()).unwrap();
+    fs::write(&compute_path, compute_shader.as_binary_u8()).unwrap();
+    generate_layout(Path::new(&out_dir));
     println!("cargo:rerun-if-changed=build.rs");
 }
\ No newline at end of file
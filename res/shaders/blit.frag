@@ -0,0 +1,10 @@
+#version 450
+
+layout(location = 0) out vec4 outColor;
+
+layout(set = 0, binding = 0) uniform texture2D image;
+layout(set = 0, binding = 1) uniform sampler image_sampler;
+
+void main() {
+    outColor = texelFetch(sampler2D(image, image_sampler), ivec2(gl_FragCoord.xy), 0);
+}
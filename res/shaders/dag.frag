@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 0) out vec4 outColor;
+
+// Uniforms, Frame, Node and DagNode, generated from the Rust definitions
+#include "layout.glsl"
+
+layout(std430, set = 1, binding = 0) buffer dag {
+    DagNode data[];
+};
+
+#include "traverse.glsl"
+#include "dag.glsl"
+
+void main()
+{
+    outColor = trace(generate_ray(gl_FragCoord.xy));
+}
@@ -0,0 +1,20 @@
+// The traverse.glsl hooks for nodes that list their 8 sub voxels, the DagNodes of the sparse
+// voxel DAG or the Nodes of the octree, which have the same fields. Include it after
+// traverse.glsl, the octree buffer has to be named `data`.
+
+bool is_leaf(int node) {
+    for (int i = 0; i < 8; i++) {
+        if (data[node].sub_voxels[i] != 0) {
+            return false;
+        }
+    }
+    return true;
+}
+
+int material(int node) {
+    return data[node].material_id;
+}
+
+int fetch_child(int node, int subvoxel) {
+    return data[node].sub_voxels[subvoxel];
+}
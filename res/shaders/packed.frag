@@ -0,0 +1,19 @@
+#version 450
+
+layout(location = 0) out vec4 outColor;
+
+// Uniforms, Frame, Node and DagNode, generated from the Rust definitions
+#include "layout.glsl"
+
+// PackedOctree::to_words, the nodes as NODE_WORDS words each followed by the leaf materials
+layout(std430, set = 1, binding = 0) buffer packed {
+    uint data[];
+};
+
+#include "traverse.glsl"
+#include "packed.glsl"
+
+void main()
+{
+    outColor = trace(generate_ray(gl_FragCoord.xy));
+}
@@ -0,0 +1,28 @@
+// The traverse.glsl hooks for PackedOctree::to_words. Include it after traverse.glsl, the words
+// have to be a uint buffer named `data`.
+//
+// Nodes are referenced by their index, leaves by -1 - the index of their material and empty
+// sub voxels by 0, which is the root and never a sub voxel.
+
+#define NODE_WORDS 3
+
+bool is_leaf(int node) {
+    return node < 0;
+}
+
+int material(int node) {
+    return int(data[-1 - node]);
+}
+
+int fetch_child(int node, int subvoxel) {
+    uint masks = data[NODE_WORDS * node];
+    uint bit = 1u << subvoxel;
+    if ((masks & bit) == 0) {
+        return 0;
+    }
+    uint leaves = masks >> 8;
+    if ((leaves & bit) != 0) {
+        return -1 - int(data[NODE_WORDS * node + 2] + uint(bitCount(leaves & (bit - 1))));
+    }
+    return int(data[NODE_WORDS * node + 1] + uint(bitCount(masks & ~leaves & (bit - 1))));
+}
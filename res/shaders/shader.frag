@@ -3,23 +3,8 @@
 layout(location = 0) out vec4 outColor;
 layout(location = 1) out vec4 outColorDebug;
 
-layout(std140, set = 0, binding = 0) uniform Uniforms {
-    vec3 view_pos;
-    vec3 view_dir;
-    vec3 view_up;
-    vec3 view_right;
-    float fov;
-    int width;
-    int height;
-    float octree_size;
-    int octree_depth;
-} uniforms;
-
-struct Node {
-    int material_id;
-    int sub_voxels[8];
-	int ropes[6];
-};
+// Uniforms, Frame, Node and DagNode, generated from the Rust definitions
+#include "layout.glsl"
 
 layout(std430, set = 1, binding = 0) buffer octree {
     Node data[];
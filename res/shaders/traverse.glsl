@@ -0,0 +1,158 @@
+// Stack based traversal shared by dag.frag, packed.frag and traverse.comp. Include it after
+// layout.glsl and the octree buffer, followed by dag.glsl or packed.glsl, which implement the
+// hooks below for their encoding of the nodes. The root is node 0.
+
+// whether `node` has no sub voxels
+bool is_leaf(int node);
+// material of the leaf `node`
+int material(int node);
+// sub voxel `subvoxel` of `node`, 0 for an empty octant
+int fetch_child(int node, int subvoxel);
+
+// deepest level the stack holds, Dag::MAX_DEPTH, PackedOctree::MAX_DEPTH and
+// ComputeRenderer::MAX_DEPTH on the Rust side
+#define MAX_DEPTH 12
+#define MAX_STEPS 100000
+#define big 10e10
+
+const int EMPTY = 0;
+
+struct Ray {
+    vec3 origin;
+    vec3 dir;
+    vec3 invDir;
+};
+
+float size = uniforms.octree_size;
+// nodes on the path from the root to the current cell, indexed by level
+int stack[MAX_DEPTH + 1];
+
+Ray generate_ray(vec2 pixel)  {
+    float x_ratio = pixel.x / float(uniforms.width);
+    float y_ratio = pixel.y / float(uniforms.height);
+    float aspect = float(uniforms.width) / float(uniforms.height);
+    float a = tan(uniforms.fov/2.0);
+    float a2 = a/aspect;
+    vec3 view_center = uniforms.view_pos + uniforms.view_dir;
+
+    vec3 target = view_center + uniforms.view_right * a * (2 * x_ratio - 1) + uniforms.view_up * a2 * (2 * y_ratio - 1);
+    vec3 dir = normalize(target-uniforms.view_pos);
+    return Ray(uniforms.view_pos, dir, vec3(1.0/dir.x, 1.0/dir.y, 1.0/dir.z));
+}
+
+float cell_size(int level) {
+    return 2.0 * size / float(1 << level);
+}
+
+// The color of the first solid cell along the ray, its normal mapped to [0, 1], or black if
+// the ray leaves the octree.
+vec4 trace(Ray ray)
+{
+    vec3 tMinus = (-size - ray.origin) * ray.invDir;
+    vec3 tPlus = (size - ray.origin) * ray.invDir;
+    vec3 tMin = min(tMinus, tPlus);
+    vec3 tMax = max(tMinus, tPlus);
+    float tsMin = max(tMin.x, max(tMin.y, tMin.z));
+    float tsMax = min(tMax.x, min(tMax.y, tMax.z));
+    if (tsMin > tsMax || tsMax < 0) {
+        return vec4(0, 0, 0, 1);
+    }
+
+    int entry_axis = tMin.x == tsMin ? 0 : (tMin.y == tsMin ? 1 : 2);
+    float t = max(tsMin, 0);
+    vec3 point = ray.origin + ray.dir * t;
+    vec3 normal = vec3(0);
+    normal[entry_axis] = -sign(ray.dir[entry_axis]);
+
+    // the face the ray crossed last, used to pick sub voxels along that axis without relying
+    // on the floating point position
+    int crossing_axis = -1;
+    int crossing_level = 0;
+    int crossing_coord = 0;
+    if (tsMin > 0) {
+        point[entry_axis] = normal[entry_axis] * size;
+        crossing_axis = entry_axis;
+    }
+
+    int level = 0;
+    ivec3 coords = ivec3(0);
+    stack[0] = 0;
+
+    for (int i = 0; i < MAX_STEPS; i++) {
+        int node = stack[level];
+        bool octant = false;
+        while (!is_leaf(node)) {
+            float half_size = cell_size(level + 1);
+            int subvoxel = 0;
+            for (int axis = 0; axis < 3; axis++) {
+                int bit;
+                if (axis == crossing_axis) {
+                    if (level < crossing_level) {
+                        bit = (crossing_coord >> (crossing_level - level - 1)) & 1;
+                    } else {
+                        bit = ray.dir[axis] > 0 ? 0 : 1;
+                    }
+                } else {
+                    float center = -size + float(2 * coords[axis] + 1) * half_size;
+                    bit = int(point[axis] > center || (point[axis] == center && ray.dir[axis] >= 0));
+                }
+                subvoxel |= bit << axis;
+                coords[axis] = coords[axis] * 2 + bit;
+            }
+            level++;
+            int child = fetch_child(node, subvoxel);
+            if (child == 0) {
+                octant = true;
+                break;
+            }
+            stack[level] = child;
+            node = child;
+        }
+
+        if (!octant && material(node) != EMPTY) {
+            return vec4(normal * 0.5 + 0.5, 1.0);
+        }
+
+        float cell = cell_size(level);
+        float t_exit = big;
+        int exit_axis = 0;
+        float exit_bound = 0;
+        for (int axis = 0; axis < 3; axis++) {
+            if (ray.dir[axis] == 0) {
+                continue;
+            }
+            float bound = -size + float(coords[axis] + int(ray.dir[axis] > 0)) * cell;
+            float t_axis = (bound - ray.origin[axis]) * ray.invDir[axis];
+            if (t_axis < t_exit) {
+                t_exit = t_axis;
+                exit_axis = axis;
+                exit_bound = bound;
+            }
+        }
+
+        int step = ray.dir[exit_axis] > 0 ? 1 : -1;
+        int neighbor = coords[exit_axis] + step;
+        if (neighbor < 0 || neighbor >= (1 << level)) {
+            break;
+        }
+
+        t = max(t_exit, t);
+        point = ray.origin + ray.dir * t;
+        point[exit_axis] = exit_bound;
+        normal = vec3(0);
+        normal[exit_axis] = -float(step);
+        crossing_axis = exit_axis;
+        crossing_level = level;
+        crossing_coord = neighbor;
+
+        // climb up to the deepest node that also contains the neighboring cell
+        int parent = level - 1;
+        while ((neighbor >> (level - parent)) != (coords[exit_axis] >> (level - parent))) {
+            parent--;
+        }
+        coords[exit_axis] = neighbor;
+        coords >>= (level - parent);
+        level = parent;
+    }
+    return vec4(0, 0, 0, 1);
+}
@@ -0,0 +1,202 @@
+use cgmath::{InnerSpace, Vector2, Vector3};
+
+use crate::octree::{EditError, Node, Octree};
+
+// Brushes for editing, applied in place top-down like the terrain: cells completely inside the
+// shape get its material, cells outside are left alone and only cells the surface passes
+// through are subdivided down to `depth`. A `material_id` of EMPTY carves the shape out
+// instead. Afterwards only the ropes around the smallest cell containing every change are
+// regenerated, which is a full rebuild when the shape spans the middle of the tree.
+impl Octree {
+    pub fn fill_sphere(&mut self, center: Vector3<f32>, radius: f32, depth: i32, material_id: i32) -> Result<(), EditError> {
+        self.fill_sdf(depth, material_id, &|p| (p - center).magnitude() - radius)
+    }
+
+    pub fn fill_box(&mut self, min: Vector3<f32>, max: Vector3<f32>, depth: i32, material_id: i32) -> Result<(), EditError> {
+        let center = (min + max) * 0.5;
+        let half_extent = (max - min) * 0.5;
+        self.fill_sdf(depth, material_id, &|p| {
+            let q = (p - center).map(f32::abs) - half_extent;
+            q.map(|q| q.max(0.0)).magnitude() + q.x.max(q.y).max(q.z).min(0.0)
+        })
+    }
+
+    // Upright cylinder, z is up, standing on `base`.
+    pub fn fill_cylinder(
+        &mut self,
+        base: Vector3<f32>,
+        radius: f32,
+        height: f32,
+        depth: i32,
+        material_id: i32,
+    ) -> Result<(), EditError> {
+        self.fill_sdf(depth, material_id, &|p| {
+            let q = Vector2::new(
+                (p.truncate() - base.truncate()).magnitude() - radius,
+                (p.z - base.z - height * 0.5).abs() - height * 0.5,
+            );
+            q.map(|q| q.max(0.0)).magnitude() + q.x.max(q.y).min(0.0)
+        })
+    }
+
+    // Fills everything where `sdf` is negative. `sdf` may not change faster than the distance to
+    // the point it is evaluated at, which holds for the exact signed distance of any shape.
+    pub fn fill_sdf(&mut self, depth: i32, material_id: i32, sdf: &dyn Fn(Vector3<f32>) -> f32) -> Result<(), EditError> {
+        Self::check_depth(depth)?;
+        self.depth = self.depth.max(depth);
+        if let Some((level, coords)) = self.fill_internal(0, 0, [0; 3], depth, material_id, sdf) {
+            self.update_ropes(coords, level);
+        }
+        Ok(())
+    }
+
+    // Whether the cell is inside the shape, outside of it, or None if the surface passes through
+    // it. Cells at `depth` are inside if their center is.
+    fn classify_cell(&self, level: i32, coords: [i32; 3], depth: i32, sdf: &dyn Fn(Vector3<f32>) -> f32) -> Option<bool> {
+        let (min, max) = self.cell_bounds(level, coords);
+        let distance = sdf((min + max) * 0.5);
+        let radius = if level == depth { 0.0 } else { (max - min).magnitude() * 0.5 };
+        if distance + radius <= 0.0 {
+            Some(true)
+        } else if distance - radius > 0.0 {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    // Applies the shape to the subtree of `index`. Returns the level and coordinates of the
+    // smallest cell containing every change, or None if nothing changed.
+    fn fill_internal(
+        &mut self,
+        index: i32,
+        level: i32,
+        coords: [i32; 3],
+        depth: i32,
+        material_id: i32,
+        sdf: &dyn Fn(Vector3<f32>) -> f32,
+    ) -> Option<(i32, [i32; 3])> {
+        let node = self.data[index as usize];
+        match self.classify_cell(level, coords, depth, sdf) {
+            Some(false) => return None,
+            Some(true) => {
+                if node.is_leaf() && node.material_id == material_id {
+                    return None;
+                }
+                self.release_sub_voxels(index);
+                self.data[index as usize].material_id = material_id;
+                return Some((level, coords));
+            }
+            None => {}
+        }
+        if node.is_leaf() {
+            if node.material_id == material_id {
+                return None;
+            }
+            self.subdivide(index);
+        }
+
+        let mut changed = vec![];
+        for i in 0..8 {
+            let child_coords = [0, 1, 2].map(|axis| coords[axis] * 2 + ((i as i32 >> axis) & 1));
+            let mut sub_voxel = self.data[index as usize].sub_voxels[i];
+            if sub_voxel == 0 {
+                // empty octants only need a node if the shape fills some of them
+                if material_id == Self::EMPTY || self.classify_cell(level + 1, child_coords, depth, sdf) == Some(false) {
+                    continue;
+                }
+                sub_voxel = self.allocate(Node { level: level + 1, ..Default::default() });
+                self.data[index as usize].sub_voxels[i] = sub_voxel;
+            }
+            changed.extend(self.fill_internal(sub_voxel, level + 1, child_coords, depth, material_id, sdf));
+        }
+        self.collapse(index);
+
+        // nodes added below this one or merged into it change the ropes around it, like in `set`
+        if self.data[index as usize].sub_voxels != node.sub_voxels {
+            return Some((level, coords));
+        }
+        match changed[..] {
+            [] => None,
+            [cell] => Some(cell),
+            _ => Some((level, coords)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Vector3};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::octree::tests::{assert_ropes_rebuilt, assert_voxels, random_octree, voxel};
+    use crate::octree::{EditError, Octree};
+
+    #[test]
+    fn sphere_fills_the_cells_with_their_center_inside() {
+        let mut rng = StdRng::seed_from_u64(24);
+        for _ in 0..20 {
+            let mut octree = random_octree(&mut rng, 4);
+            let before = octree.data.clone();
+            let before = Octree { data: before, ..Octree::new(octree.depth, octree.size).unwrap() };
+
+            let center = Vector3::new(rng.gen_range(-8.0..8.0), rng.gen_range(-8.0..8.0), rng.gen_range(-8.0..8.0));
+            let radius = rng.gen_range(0.5..6.0);
+            let material_id = rng.gen_range(0..4);
+            octree.fill_sphere(center, radius, 5, material_id).unwrap();
+            assert_eq!(octree.validate(), Ok(()));
+            assert_ropes_rebuilt(&octree);
+            assert_eq!(octree.depth, 5);
+
+            assert_voxels(&octree, 5, |coords| {
+                let cell_center = Vector3::from(coords.map(|coord| -8.0 + (coord as f32 + 0.5) * 0.5));
+                if (cell_center - center).magnitude() - radius <= 0.0 {
+                    material_id
+                } else {
+                    voxel(&before, 5, coords)
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn brushes_keep_the_tree_collapsed() {
+        let mut octree = Octree::new(3, 8.0).unwrap();
+        octree.fill_box(Vector3::new(-8.0, -8.0, -8.0), Vector3::new(8.0, 8.0, 0.0), 3, 2).unwrap();
+        // the lower half of the cube, four leaves of the root
+        assert_eq!(octree.data.iter().filter(|node| node.level == 1 && node.material_id == 2).count(), 4);
+        octree.fill_box(Vector3::new(-8.0, -8.0, 0.0), Vector3::new(8.0, 8.0, 8.0), 3, 2).unwrap();
+        assert!(octree.data[0].is_leaf());
+        assert_eq!(octree.data[0].material_id, 2);
+
+        octree.fill_cylinder(Vector3::new(0.0, 0.0, -8.0), 4.0, 16.0, 3, Octree::EMPTY).unwrap();
+        assert_eq!(octree.validate(), Ok(()));
+        assert_ropes_rebuilt(&octree);
+        assert_eq!(voxel(&octree, 3, [4, 4, 0]), Octree::EMPTY);
+        assert_eq!(voxel(&octree, 3, [0, 0, 7]), 2);
+        let live = octree.data.len() - octree.free.len();
+        octree.fill_cylinder(Vector3::new(0.0, 0.0, -8.0), 4.0, 16.0, 3, Octree::EMPTY).unwrap();
+        assert_eq!(octree.data.len() - octree.free.len(), live);
+    }
+
+    #[test]
+    fn small_brushes_only_update_their_cell() {
+        let mut octree = Octree::new_wall(4, 8.0);
+        octree.optimize();
+        let sdf = |p: Vector3<f32>| (p - Vector3::new(-5.0, -5.0, 5.0)).magnitude() - 1.5;
+        // the voxel centers inside are all in the cell at level 2 from -8 to -4 along x and y
+        // and 4 to 8 along z
+        assert_eq!(octree.fill_internal(0, 0, [0; 3], 4, 3, &sdf), Some((2, [0, 0, 3])));
+        octree.update_ropes([0, 0, 3], 2);
+        assert_eq!(octree.validate(), Ok(()));
+        assert_eq!(octree.fill_internal(0, 0, [0; 3], 4, 3, &sdf), None);
+    }
+
+    #[test]
+    fn rejects_invalid_depths() {
+        let mut octree = Octree::new(3, 8.0).unwrap();
+        let err = octree.fill_sphere(Vector3::new(0.0, 0.0, 0.0), 1.0, 31, 1);
+        assert_eq!(err, Err(EditError::InvalidDepth(31)));
+    }
+}
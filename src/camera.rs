@@ -0,0 +1,95 @@
+use cgmath::{InnerSpace, Vector3};
+use winit::event::{ElementState, MouseButton, VirtualKeyCode};
+
+// Free flying camera, z is up. A yaw of 0 looks along +x, positive pitch looks up.
+pub struct Camera {
+    pub position: Vector3<f32>,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fov: f32,
+    // units per second
+    pub speed: f32,
+    // radians per pixel of mouse motion
+    pub sensitivity: f32,
+    // whether the keys for the negative and positive direction of each axis are held
+    forward: [bool; 2],
+    right: [bool; 2],
+    up: [bool; 2],
+    looking: bool,
+}
+
+impl Camera {
+    pub fn looking_at(position: Vector3<f32>, target: Vector3<f32>) -> Self {
+        let dir = (target - position).normalize();
+        Self {
+            position,
+            yaw: dir.y.atan2(dir.x),
+            pitch: dir.z.asin(),
+            fov: std::f32::consts::PI * 90.0 / 180.0,
+            speed: 10.0,
+            sensitivity: 0.003,
+            forward: [false; 2],
+            right: [false; 2],
+            up: [false; 2],
+            looking: false,
+        }
+    }
+
+    pub fn view_dir(&self) -> Vector3<f32> {
+        Vector3::new(self.pitch.cos() * self.yaw.cos(), self.pitch.cos() * self.yaw.sin(), self.pitch.sin())
+    }
+
+    // view direction, right and up vectors
+    pub fn basis(&self) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+        let view_dir = self.view_dir();
+        let right = view_dir.cross(Vector3::unit_z()).normalize();
+        let up = right.cross(view_dir);
+        (view_dir, right, up)
+    }
+
+    pub fn is_movement_key(keycode: VirtualKeyCode) -> bool {
+        matches!(
+            keycode,
+            VirtualKeyCode::W | VirtualKeyCode::A | VirtualKeyCode::S | VirtualKeyCode::D | VirtualKeyCode::Q | VirtualKeyCode::E
+        )
+    }
+
+    pub fn process_key(&mut self, keycode: VirtualKeyCode, state: ElementState) {
+        let pressed = state == ElementState::Pressed;
+        match keycode {
+            VirtualKeyCode::W => self.forward[1] = pressed,
+            VirtualKeyCode::S => self.forward[0] = pressed,
+            VirtualKeyCode::D => self.right[1] = pressed,
+            VirtualKeyCode::A => self.right[0] = pressed,
+            VirtualKeyCode::E => self.up[1] = pressed,
+            VirtualKeyCode::Q => self.up[0] = pressed,
+            _ => {}
+        }
+    }
+
+    // The mouse only turns the camera while the left button is held.
+    pub fn process_button(&mut self, button: MouseButton, state: ElementState) {
+        if button == MouseButton::Left {
+            self.looking = state == ElementState::Pressed;
+        }
+    }
+
+    pub fn process_mouse(&mut self, dx: f64, dy: f64) {
+        if !self.looking {
+            return;
+        }
+        let limit = std::f32::consts::FRAC_PI_2 - 0.01;
+        self.yaw -= dx as f32 * self.sensitivity;
+        self.pitch = (self.pitch - dy as f32 * self.sensitivity).clamp(-limit, limit);
+    }
+
+    // Moves by the held keys for `dt` seconds.
+    pub fn update(&mut self, dt: f32) {
+        let axis = |keys: [bool; 2]| keys[1] as i32 as f32 - keys[0] as i32 as f32;
+        let (view_dir, right, _) = self.basis();
+        let movement = view_dir * axis(self.forward) + right * axis(self.right) + Vector3::unit_z() * axis(self.up);
+        if movement != Vector3::new(0.0, 0.0, 0.0) {
+            self.position += movement.normalize() * self.speed * dt;
+        }
+    }
+}
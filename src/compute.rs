@@ -0,0 +1,214 @@
+use crate::{
+    compile_shader_alternative, create_render_pipeline, draw_fullscreen, fallback_render_pipeline, shader_dir,
+    with_validation,
+};
+
+const WORKGROUP_SIZE: u32 = 8;
+const TARGET_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+// Traces the rays in a compute shader that writes into a storage texture, a full screen pass
+// then copies the texture to the render target. The uniform and octree bind groups are shared
+// with the fragment shader path, the storage texture is bound as group 2. It starts out with the
+// blank shaders from build.rs, `reload` compiles the ones in res/shaders.
+pub struct ComputeRenderer {
+    // selects the node type of the octree bind group in traverse.comp
+    defines: &'static [&'static str],
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::ComputePipeline,
+    target_layout: wgpu::BindGroupLayout,
+    target_bind_group: wgpu::BindGroup,
+    blit_pipeline_layout: wgpu::PipelineLayout,
+    blit_pipeline: wgpu::RenderPipeline,
+    blit_layout: wgpu::BindGroupLayout,
+    blit_bind_group: wgpu::BindGroup,
+}
+
+impl ComputeRenderer {
+    // same as MAX_DEPTH in traverse.glsl, the size of its stack
+    pub const MAX_DEPTH: i32 = 12;
+
+    pub fn new(
+        device: &wgpu::Device,
+        uniform_layout: &wgpu::BindGroupLayout,
+        octree_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        defines: &'static [&'static str],
+    ) -> Self {
+        let target_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: TARGET_FORMAT,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            }],
+        });
+        let blit_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[uniform_layout, octree_layout, &target_layout],
+            push_constant_ranges: &[],
+        });
+        let blit_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&blit_layout],
+            push_constant_ranges: &[],
+        });
+
+        let fallback: Box<[u8]> =
+            Box::from(include_bytes!(concat!(env!("OUT_DIR"), "/fallback_comp.spv")).as_slice());
+        let pipeline = Self::pipeline_from_spirv(device, &pipeline_layout, &fallback);
+        let blit_pipeline = fallback_render_pipeline(device, &blit_pipeline_layout, format);
+        let (target_bind_group, blit_bind_group) =
+            Self::create_target(device, &target_layout, &blit_layout, width, height);
+        Self {
+            defines,
+            format,
+            width,
+            height,
+            pipeline_layout,
+            pipeline,
+            target_layout,
+            target_bind_group,
+            blit_pipeline_layout,
+            blit_pipeline,
+            blit_layout,
+            blit_bind_group,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        defines: &[&str],
+    ) -> Result<wgpu::ComputePipeline, String> {
+        let code = compile_shader_alternative(&shader_dir(), "traverse.comp", shaderc::ShaderKind::Compute, defines)?;
+        with_validation(device, || Self::pipeline_from_spirv(device, layout, &code))
+    }
+
+    fn pipeline_from_spirv(device: &wgpu::Device, layout: &wgpu::PipelineLayout, code: &[u8]) -> wgpu::ComputePipeline {
+        let module = unsafe {
+            device.create_shader_module_spirv(&wgpu::ShaderModuleDescriptorSpirV {
+                label: None,
+                source: bytemuck::cast_slice(code).into(),
+            })
+        };
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(layout),
+            module: &module,
+            entry_point: "main",
+        })
+    }
+
+    fn create_target(
+        device: &wgpu::Device,
+        target_layout: &wgpu::BindGroupLayout,
+        blit_layout: &wgpu::BindGroupLayout,
+        width: u32,
+        height: u32,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TARGET_FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+        let target_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: target_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            }],
+        });
+        let blit_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: blit_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+        (target_bind_group, blit_bind_group)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        (self.target_bind_group, self.blit_bind_group) =
+            Self::create_target(device, &self.target_layout, &self.blit_layout, width, height);
+    }
+
+    // Pipelines that fail to compile are kept as they are.
+    pub fn reload(&mut self, device: &wgpu::Device) -> Result<(), String> {
+        let mut errors = vec![];
+        match Self::create_pipeline(device, &self.pipeline_layout, self.defines) {
+            Ok(pipeline) => self.pipeline = pipeline,
+            Err(err) => errors.push(err),
+        }
+        match create_render_pipeline(device, &self.blit_pipeline_layout, self.format, "blit.frag") {
+            Ok(pipeline) => self.blit_pipeline = pipeline,
+            Err(err) => errors.push(err),
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    // `bind_groups` are the uniform and octree bind groups.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, bind_groups: &[&wgpu::BindGroup]) {
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            cpass.set_pipeline(&self.pipeline);
+            for (index, bind_group) in bind_groups.iter().enumerate() {
+                cpass.set_bind_group(index as u32, bind_group, &[]);
+            }
+            cpass.set_bind_group(bind_groups.len() as u32, &self.target_bind_group, &[]);
+            cpass.dispatch(self.width.div_ceil(WORKGROUP_SIZE), self.height.div_ceil(WORKGROUP_SIZE), 1);
+        }
+        draw_fullscreen(encoder, view, &self.blit_pipeline, &[&self.blit_bind_group]);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::octree::{Node, Octree};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    Difference,
+}
+
+// What covers a cell in one of the trees: a leaf with its material, or a node with sub voxels.
+// Empty octants are empty leaves.
+#[derive(Clone, Copy, Debug)]
+enum Operand {
+    Leaf(i32),
+    Node(i32),
+}
+
+impl CsgOp {
+    // The material of a cell where the first tree has `a` and the second `b`, or None if it
+    // depends on the sub voxels. Solid cells keep the material of the first tree.
+    fn combine(self, a: Operand, b: Operand) -> Option<i32> {
+        match (self, a, b) {
+            (CsgOp::Union, Operand::Leaf(a), Operand::Leaf(b)) => Some(if a != Octree::EMPTY { a } else { b }),
+            (CsgOp::Union, Operand::Leaf(a), _) if a != Octree::EMPTY => Some(a),
+            (CsgOp::Intersection, Operand::Leaf(Octree::EMPTY), _)
+            | (CsgOp::Intersection, _, Operand::Leaf(Octree::EMPTY)) => Some(Octree::EMPTY),
+            (CsgOp::Intersection, Operand::Leaf(a), Operand::Leaf(_)) => Some(a),
+            (CsgOp::Difference, Operand::Leaf(Octree::EMPTY), _) => Some(Octree::EMPTY),
+            (CsgOp::Difference, _, Operand::Leaf(b)) if b != Octree::EMPTY => Some(Octree::EMPTY),
+            (CsgOp::Difference, Operand::Leaf(a), Operand::Leaf(_)) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+impl Octree {
+    // Solid wherever either tree is, cells solid in both keep the material of `self`.
+    pub fn union(&self, other: &Octree) -> Result<Self, String> {
+        self.combine(other, CsgOp::Union)
+    }
+
+    // Solid where both trees are, with the material of `self`.
+    pub fn intersection(&self, other: &Octree) -> Result<Self, String> {
+        self.combine(other, CsgOp::Intersection)
+    }
+
+    // `self` with everything that is solid in `other` carved out.
+    pub fn difference(&self, other: &Octree) -> Result<Self, String> {
+        self.combine(other, CsgOp::Difference)
+    }
+
+    // Both trees have to span the same cube, their depths can differ. The result is optimized
+    // and has its ropes generated.
+    pub fn combine(&self, other: &Octree, op: CsgOp) -> Result<Self, String> {
+        if self.size != other.size {
+            return Err(format!("can not combine octrees of size {} and {}", self.size, other.size));
+        }
+        let mut data = vec![];
+        Self::combine_internal(
+            op,
+            (&self.data, Self::operand(&self.data, 0)),
+            (&other.data, Self::operand(&other.data, 0)),
+            0,
+            &mut data,
+        );
+        let mut octree = Self { data, depth: self.depth.max(other.depth), size: self.size, free: vec![] };
+        octree.optimize();
+        Ok(octree)
+    }
+
+    fn combine_internal(op: CsgOp, a: (&[Node], Operand), b: (&[Node], Operand), level: i32, data: &mut Vec<Node>) -> i32 {
+        let index = data.len();
+        if let Some(material_id) = op.combine(a.1, b.1) {
+            data.push(Node { material_id, level, ..Default::default() });
+            return index as i32;
+        }
+        data.push(Node { level, ..Default::default() });
+        for i in 0..8 {
+            let sub_voxel = Self::combine_internal(
+                op,
+                (a.0, Self::sub_operand(a.0, a.1, i)),
+                (b.0, Self::sub_operand(b.0, b.1, i)),
+                level + 1,
+                data,
+            );
+            data[index].sub_voxels[i] = sub_voxel;
+        }
+        index as i32
+    }
+
+    fn operand(data: &[Node], index: i32) -> Operand {
+        let node = &data[index as usize];
+        if node.is_leaf() {
+            Operand::Leaf(node.material_id)
+        } else {
+            Operand::Node(index)
+        }
+    }
+
+    // A leaf covers its octants with its own material.
+    fn sub_operand(data: &[Node], operand: Operand, i: usize) -> Operand {
+        match operand {
+            Operand::Leaf(material_id) => Operand::Leaf(material_id),
+            Operand::Node(index) => match data[index as usize].sub_voxels[i] {
+                0 => Operand::Leaf(Self::EMPTY),
+                sub_voxel => Self::operand(data, sub_voxel),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::CsgOp;
+    use crate::octree::Octree;
+
+    // `n` cells along each axis, in blocks of 4 that are either one material or noise, so the
+    // trees have both large leaves and fine detail
+    fn random_grid(rng: &mut StdRng, n: usize) -> Vec<i32> {
+        let blocks = (0..(n / 4).pow(3)).map(|_| rng.gen_range(-1..3)).collect::<Vec<i32>>();
+        (0..n * n * n)
+            .map(|i| {
+                let (x, y, z) = (i % n / 4, i / n % n / 4, i / (n * n) / 4);
+                match blocks[x + n / 4 * (y + n / 4 * z)] {
+                    -1 => rng.gen_range(0..3),
+                    material_id => material_id,
+                }
+            })
+            .collect()
+    }
+
+    fn expected(op: CsgOp, a: i32, b: i32) -> i32 {
+        match op {
+            CsgOp::Union if a != Octree::EMPTY => a,
+            CsgOp::Union => b,
+            CsgOp::Intersection if b != Octree::EMPTY => a,
+            CsgOp::Difference if b == Octree::EMPTY => a,
+            _ => Octree::EMPTY,
+        }
+    }
+
+    #[test]
+    fn matches_dense_ground_truth() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..20 {
+            // the second tree is one level shallower
+            let a = random_grid(&mut rng, 16);
+            let b = random_grid(&mut rng, 8);
+            let first = Octree::from_dense([16; 3], &a, 8.0).unwrap();
+            let second = Octree::from_dense([8; 3], &b, 8.0).unwrap();
+            for op in [CsgOp::Union, CsgOp::Intersection, CsgOp::Difference] {
+                for (result, swapped) in [(first.combine(&second, op), false), (second.combine(&first, op), true)] {
+                    let result = result.unwrap();
+                    assert_eq!(result.validate(), Ok(()));
+                    assert_eq!(result.depth, 4);
+                    let dense = result.to_dense(4).unwrap();
+                    for (i, &material_id) in dense.iter().enumerate() {
+                        let (x, y, z) = (i % 16 / 2, i / 16 % 16 / 2, i / 256 / 2);
+                        let (a, b) = (a[i], b[x + 8 * (y + 8 * z)]);
+                        let expected = if swapped { expected(op, b, a) } else { expected(op, a, b) };
+                        assert_eq!(material_id, expected, "{:?} at cell {}", op, i);
+                    }
+                }
+            }
+        }
+    }
+    #[test]
+    fn rejects_trees_of_different_sizes() {
+        let first = Octree::new_wall(2, 8.0);
+        let second = Octree::new_wall(2, 4.0);
+        assert!(first.union(&second).is_err());
+        assert!(second.difference(&first).is_err());
+    }
+}
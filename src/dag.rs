@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+use bytemuck::{Pod, Zeroable};
+
+use crate::octree::{Node, Octree};
+
+// Octree where identical subtrees are stored once. Nodes are shared between parents, so there
+// are no ropes or levels, the shader keeps the path to the current node on a stack instead.
+pub struct Dag {
+    pub(crate) data: Vec<DagNode>,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Zeroable, Pod)]
+pub struct DagNode {
+    pub(crate) material_id: i32,
+    pub(crate) sub_voxels: [i32; 8],
+}
+
+impl Dag {
+    // same as MAX_DEPTH in traverse.glsl, the size of its stack
+    pub const MAX_DEPTH: i32 = 12;
+
+    pub fn from_octree(octree: &Octree) -> Result<Self, String> {
+        if octree.depth > Self::MAX_DEPTH {
+            return Err(format!("depth {} is deeper than the {} levels dag.frag can trace", octree.depth, Self::MAX_DEPTH));
+        }
+        // the root has to stay at index 0, it is filled in once its sub voxels are known
+        let mut data = vec![DagNode::default()];
+        let mut nodes = HashMap::new();
+        let root = Self::from_octree_internal(&octree.data, 0, &mut data, &mut nodes);
+        data[0] = root;
+        Ok(Self { data })
+    }
+
+    pub fn data(&self) -> &[DagNode] {
+        &self.data
+    }
+
+    fn from_octree_internal(
+        octree: &[Node],
+        index: i32,
+        data: &mut Vec<DagNode>,
+        nodes: &mut HashMap<DagNode, i32>,
+    ) -> DagNode {
+        let node = &octree[index as usize];
+        let mut dag_node = DagNode { material_id: node.material_id, sub_voxels: [0; 8] };
+        for (i, sub_voxel) in node.sub_voxels.iter().enumerate() {
+            if *sub_voxel == 0 {
+                continue;
+            }
+            let child = Self::from_octree_internal(octree, *sub_voxel, data, nodes);
+            if child.material_id == Octree::EMPTY && child.sub_voxels == [0; 8] {
+                continue;
+            }
+            dag_node.sub_voxels[i] = *nodes.entry(child).or_insert_with(|| {
+                data.push(child);
+                data.len() as i32 - 1
+            });
+        }
+        dag_node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::Dag;
+    use crate::octree::tests::{assert_voxels, child_index, random_octree};
+    use crate::octree::Octree;
+
+    // Material of the voxel at `coords` in the 2^depth grid
+    fn lookup(dag: &Dag, depth: i32, coords: [i32; 3]) -> i32 {
+        let mut node = &dag.data[0];
+        for shift in (0..depth).rev() {
+            if node.sub_voxels == [0; 8] {
+                break;
+            }
+            match node.sub_voxels[child_index(coords, shift)] {
+                0 => return Octree::EMPTY,
+                sub_voxel => node = &dag.data[sub_voxel as usize],
+            }
+        }
+        node.material_id
+    }
+
+    #[test]
+    fn matches_the_octree() {
+        let mut rng = StdRng::seed_from_u64(4);
+        for _ in 0..20 {
+            let octree = random_octree(&mut rng, 4);
+            let dag = Dag::from_octree(&octree).unwrap();
+            assert!(dag.data.len() <= octree.data.len());
+            assert_voxels(&octree, 4, |coords| lookup(&dag, 4, coords));
+        }
+    }
+
+    #[test]
+    fn shares_identical_subtrees() {
+        let octree = Octree::new_wall(6, 8.0);
+        let dag = Dag::from_octree(&octree).unwrap();
+        // the root and one node per level below it
+        assert_eq!(dag.data.len(), 7);
+        for node in &dag.data {
+            let mut sub_voxels = node.sub_voxels.iter().filter(|&&sub_voxel| sub_voxel != 0);
+            if let Some(first) = sub_voxels.next() {
+                assert!(sub_voxels.all(|sub_voxel| sub_voxel == first));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_trees_too_deep_for_the_shader() {
+        for (depth, ok) in [(12, true), (13, false)] {
+            let mut octree = Octree::new(depth, 8.0).unwrap();
+            octree.set(5, 0, 0, depth, 1).unwrap();
+            assert_eq!(Dag::from_octree(&octree).is_ok(), ok, "depth {}", depth);
+        }
+    }
+}
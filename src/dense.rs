@@ -0,0 +1,200 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::octree::{Node, Octree};
+
+// Conversion from and to flat grids of materials, indexed by x + dims[0] * (y + dims[1] * z).
+impl Octree {
+    // The grid starts at the negative corner of the cube, the depth of the tree is the next power
+    // of two of its largest dimension. Cells past the end of the grid are empty.
+    pub fn from_dense(dims: [usize; 3], materials: &[i32], size: f32) -> Result<Self, String> {
+        let cells = dims.iter().try_fold(1usize, |cells, &dim| cells.checked_mul(dim));
+        if cells != Some(materials.len()) {
+            return Err(format!("a {:?} grid does not have {} materials", dims, materials.len()));
+        }
+        let extent = dims.iter().copied().max().unwrap_or(0);
+        let mut depth = 0;
+        while depth <= Self::MAX_DEPTH && 1 << depth < extent {
+            depth += 1;
+        }
+        if depth > Self::MAX_DEPTH {
+            return Err(format!("a {:?} grid is too large for an octree", dims));
+        }
+
+        let mut data = vec![];
+        Self::from_dense_internal(dims, materials, depth, 0, [0; 3], &mut data);
+        Self::generate_ropes(&mut data);
+        Ok(Self { data, depth, size, free: vec![] })
+    }
+
+    // Pushes the subtree of the cell at `coords` on `level` and returns its index. Uniform cells
+    // become leaves as soon as their sub voxels are known, so the grid is never padded.
+    fn from_dense_internal(dims: [usize; 3], materials: &[i32], depth: i32, level: i32, coords: [i32; 3], data: &mut Vec<Node>) -> i32 {
+        let index = data.len();
+        let start = coords.map(|coord| (coord as usize) << (depth - level));
+        if (0..3).any(|axis| start[axis] >= dims[axis]) {
+            data.push(Node { material_id: Self::EMPTY, level, ..Default::default() });
+            return index as i32;
+        }
+        if level == depth {
+            let material_id = materials[start[0] + dims[0] * (start[1] + dims[1] * start[2])];
+            data.push(Node { material_id, level, ..Default::default() });
+            return index as i32;
+        }
+
+        data.push(Node { level, ..Default::default() });
+        for i in 0..8 {
+            let child_coords = [0, 1, 2].map(|axis| coords[axis] * 2 + ((i as i32 >> axis) & 1));
+            let sub_voxel = Self::from_dense_internal(dims, materials, depth, level + 1, child_coords, data);
+            // empty leaves are the last node pushed, empty octants are not stored
+            let child = data[sub_voxel as usize];
+            if child.is_leaf() && child.material_id == Self::EMPTY {
+                data.pop();
+            } else {
+                data[index].sub_voxels[i] = sub_voxel;
+            }
+        }
+        let node = data[index];
+        let material_id = data[node.sub_voxels[0] as usize].material_id;
+        let uniform = node.sub_voxels.iter().all(|&sub_voxel| {
+            sub_voxel != 0 && data[sub_voxel as usize].is_leaf() && data[sub_voxel as usize].material_id == material_id
+        });
+        if uniform {
+            // the eight leaves directly follow their parent
+            data.truncate(index + 1);
+            data[index] = Node { material_id, level, ..Default::default() };
+        }
+        index as i32
+    }
+
+    // Samples the tree into a grid of 2^level cells along each axis. Cells with finer detail get
+    // the material that covers most of them, empty space included. Fails for levels deeper than
+    // the tree and for grids that do not fit in memory.
+    pub fn to_dense(&self, level: i32) -> Result<Vec<i32>, String> {
+        if !(0..=self.depth).contains(&level) {
+            return Err(format!("level {} is outside of the tree of depth {}", level, self.depth));
+        }
+        let too_large = || format!("a grid with 2^{} cells along each axis is too large", level);
+        let cells = 1usize
+            .checked_shl(level as u32)
+            .and_then(|n| n.checked_mul(n)?.checked_mul(n))
+            .ok_or_else(too_large)?;
+        let mut materials = Vec::new();
+        materials.try_reserve_exact(cells).map_err(|_| too_large())?;
+        materials.resize(cells, Self::EMPTY);
+        let n = 1usize << level;
+        let index = |coords: [i32; 3]| coords[0] as usize + n * (coords[1] as usize + n * coords[2] as usize);
+        // volume of each material inside the cells with finer detail, in cells at `depth`
+        let mut volumes: HashMap<usize, HashMap<i32, u128>> = HashMap::new();
+        for leaf in self.leaves() {
+            if leaf.level <= level {
+                let extent = 1 << (level - leaf.level);
+                let start = leaf.coords.map(|coord| coord * extent);
+                for z in start[2]..start[2] + extent {
+                    for y in start[1]..start[1] + extent {
+                        for x in start[0]..start[0] + extent {
+                            materials[index([x, y, z])] = leaf.material_id;
+                        }
+                    }
+                }
+            } else {
+                let cell = index(leaf.coords.map(|coord| coord >> (leaf.level - level)));
+                *volumes.entry(cell).or_default().entry(leaf.material_id).or_default() += 1u128 << (3 * (self.depth - leaf.level));
+            }
+        }
+        for (cell, volumes) in volumes {
+            let empty = (1u128 << (3 * (self.depth - level))) - volumes.values().sum::<u128>();
+            let (material_id, volume) = volumes
+                .into_iter()
+                .max_by_key(|&(material_id, volume)| (volume, Reverse(material_id)))
+                .unwrap();
+            materials[cell] = if volume >= empty { material_id } else { Self::EMPTY };
+        }
+        Ok(materials)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::octree::Octree;
+
+    #[test]
+    fn round_trip() {
+        let mut rng = StdRng::seed_from_u64(25);
+        for dims in [[1, 1, 1], [4, 4, 4], [5, 3, 7], [16, 2, 9], [1, 1, 20]] {
+            // runs of one material so some cells merge
+            let mut materials = vec![];
+            while materials.len() < dims.iter().product() {
+                let material_id = rng.gen_range(0..3);
+                materials.resize(materials.len() + rng.gen_range(1..12), material_id);
+            }
+            materials.truncate(dims.iter().product());
+
+            let octree = Octree::from_dense(dims, &materials, 8.0).unwrap();
+            assert_eq!(octree.validate(), Ok(()));
+            let n = 1 << octree.depth;
+            assert!(n >= dims.iter().copied().max().unwrap() && n / 2 < dims.iter().copied().max().unwrap());
+            // already as collapsed as `optimize` makes it
+            let mut optimized = Octree { data: octree.data.clone(), ..Octree::new(octree.depth, octree.size).unwrap() };
+            optimized.optimize();
+            assert_eq!(optimized.data.len(), octree.data.len());
+
+            let dense = octree.to_dense(octree.depth).unwrap();
+            for (i, &material_id) in dense.iter().enumerate() {
+                let (x, y, z) = (i % n, i / n % n, i / (n * n));
+                let expected = if x < dims[0] && y < dims[1] && z < dims[2] {
+                    materials[x + dims[0] * (y + dims[1] * z)]
+                } else {
+                    Octree::EMPTY
+                };
+                assert_eq!(material_id, expected, "{:?} cell {:?}", dims, (x, y, z));
+            }
+        }
+    }
+
+    #[test]
+    fn samples_coarser_levels_by_majority() {
+        // the first octant of the cube is half filled with 1 and a quarter with 2
+        let mut materials = vec![0; 64];
+        for z in 0..2 {
+            for y in 0..2 {
+                materials[4 * (y + 4 * z)] = if z == 0 { 1 } else { 2 };
+                if z == 0 {
+                    materials[1 + 4 * (y + 4 * z)] = 1;
+                }
+            }
+        }
+        let octree = Octree::from_dense([4; 3], &materials, 8.0).unwrap();
+        assert_eq!(octree.to_dense(0).unwrap(), vec![Octree::EMPTY]);
+        let coarse = octree.to_dense(1).unwrap();
+        assert_eq!(coarse[0], 1);
+        assert!(coarse[1..].iter().all(|&material_id| material_id == Octree::EMPTY));
+
+        // ties go to the smaller material, even the smallest one there is
+        let mut octree = Octree::new(1, 8.0).unwrap();
+        for index in 0..8 {
+            let material_id = if index & 4 == 0 { 3 } else { i32::MIN };
+            octree.set(index & 1, (index >> 1) & 1, index >> 2, 1, material_id).unwrap();
+        }
+        assert_eq!(octree.to_dense(0).unwrap(), vec![i32::MIN]);
+    }
+
+    #[test]
+    fn rejects_bad_sizes() {
+        assert!(Octree::from_dense([2, 2, 2], &[1; 7], 8.0).is_err());
+        assert!(Octree::from_dense([usize::MAX, 2, 1], &[], 8.0).is_err());
+        let empty = Octree::from_dense([0, 0, 0], &[], 8.0).unwrap();
+        assert_eq!((empty.depth, empty.data.len()), (0, 1));
+
+        let octree = Octree::from_dense([2, 2, 2], &[1; 8], 8.0).unwrap();
+        assert!(octree.to_dense(2).is_err());
+        assert!(octree.to_dense(-1).is_err());
+        let deep = Octree::new(Octree::MAX_DEPTH, 8.0).unwrap();
+        assert!(deep.to_dense(Octree::MAX_DEPTH).is_err());
+        // the cells can be counted but not allocated
+        assert!(deep.to_dense(20).is_err());
+    }
+}
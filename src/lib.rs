@@ -0,0 +1,18 @@
+// The octree, its encodings for the GPU, generators, importers and editing. Everything that
+// does not need a window or a device lives here so it can be used and tested on its own.
+pub mod brush;
+pub mod csg;
+pub mod dag;
+pub mod dense;
+pub mod noise;
+pub mod octree;
+pub mod packed;
+pub mod query;
+pub mod storage;
+pub mod terrain;
+pub mod tracer;
+pub mod uniforms;
+pub mod vox;
+pub mod voxelizer;
+
+include!(concat!(env!("OUT_DIR"), "/layout_check.rs"));
@@ -2,91 +2,227 @@ extern crate core;
 
 use std::time::Instant;
 
-use cgmath::{InnerSpace, Vector3, Zero};
+use cgmath::{Vector3, Zero};
 use crevice::std140::{AsStd140, Std140};
 use wgpu::util::DeviceExt;
 use winit::event::{DeviceEvent, ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::Window;
 
-use crate::octree::{Node, Octree};
+use wgpu_rust2::dag::Dag;
+use wgpu_rust2::noise::Noise;
+use wgpu_rust2::octree::{GeneratorConfig, Octree};
+use wgpu_rust2::packed::PackedOctree;
+use wgpu_rust2::uniforms::{FrameConstants, Uniforms};
+use wgpu_rust2::vox;
+use wgpu_rust2::voxelizer::{voxelize, Mesh};
 
+use crate::camera::Camera;
+use crate::compute::ComputeRenderer;
+use crate::watcher::ShaderWatcher;
+
+mod camera;
+mod compute;
 mod experiments;
-mod octree;
-
-#[repr(C)]
-#[derive(Debug, Clone, Copy, AsStd140)]
-struct Uniforms {
-    view_pos: mint::Vector3<f32>,
-    view_dir: mint::Vector3<f32>,
-    view_up: mint::Vector3<f32>,
-    view_right: mint::Vector3<f32>,
-    fov: f32,
-    width: i32,
-    height: i32,
-    octree_size: f32,
-    octree_depth: i32,
+mod offscreen;
+mod watcher;
+
+const TITLE: &str = "wgpu_rust2";
+
+// How the octree is laid out in the storage buffer the shaders read.
+#[derive(Clone, Copy, Default, PartialEq)]
+enum Encoding {
+    #[default]
+    Octree,
+    Dag,
+    Packed,
+}
+
+impl Encoding {
+    fn fragment_shader(self) -> &'static str {
+        match self {
+            Encoding::Octree => "shader.frag",
+            Encoding::Dag => "dag.frag",
+            Encoding::Packed => "packed.frag",
+        }
+    }
+
+    // defines selecting the matching node type in traverse.comp
+    fn defines(self) -> &'static [&'static str] {
+        match self {
+            Encoding::Octree => &[],
+            Encoding::Dag => &["DAG"],
+            Encoding::Packed => &["PACKED"],
+        }
+    }
+}
+
+enum Generator {
+    Random(u64),
+    Heightfield(u64),
+    Density(u64),
+}
+
+#[derive(Default)]
+struct Args {
+    encoding: Encoding,
+    scene: Option<std::path::PathBuf>,
+    solid: bool,
+    generator: Option<Generator>,
+    headless: Option<std::path::PathBuf>,
+    software: bool,
+    speed: Option<f32>,
+    compute: bool,
+}
+
+impl Args {
+    fn parse() -> Self {
+        let mut args = Self::default();
+        let seed = |value: Option<String>| value.and_then(|value| value.parse().ok()).expect("expected a seed");
+        let mut iter = std::env::args().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--dag" => args.encoding = Encoding::Dag,
+                "--packed" => args.encoding = Encoding::Packed,
+                "--load" => args.scene = Some(iter.next().expect("--load expects a path").into()),
+                "--solid" => args.solid = true,
+                "--headless" => args.headless = Some(iter.next().expect("--headless expects a path").into()),
+                "--software" => args.software = true,
+                "--compute" => args.compute = true,
+                "--speed" => {
+                    args.speed = Some(iter.next().and_then(|value| value.parse().ok()).expect("--speed expects a number"))
+                }
+                "--random" => args.generator = Some(Generator::Random(seed(iter.next()))),
+                "--heightfield" => args.generator = Some(Generator::Heightfield(seed(iter.next()))),
+                "--density" => args.generator = Some(Generator::Density(seed(iter.next()))),
+                _ => eprintln!("unknown argument {}", arg),
+            }
+        }
+        args
+    }
 }
 
 fn compile_shader_alternative(
     dir: &std::path::PathBuf,
     name: &str,
     kind: shaderc::ShaderKind,
-) -> Option<Box<[u8]>> {
-    let code = std::fs::read_to_string(dir.join(name).as_path()).unwrap();
+    defines: &[&str],
+) -> Result<Box<[u8]>, String> {
+    let code = std::fs::read_to_string(dir.join(name).as_path()).map_err(|err| format!("{}: {}", name, err))?;
     let mut compiler = shaderc::Compiler::new().unwrap();
     let mut options = shaderc::CompileOptions::new().unwrap();
     options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+    for define in defines {
+        options.add_macro_definition(define, None);
+    }
+    options.set_include_callback(|name, _, _, _| {
+        let content = if name == "layout.glsl" {
+            include_str!(concat!(env!("OUT_DIR"), "/layout.glsl")).to_string()
+        } else {
+            std::fs::read_to_string(dir.join(name)).map_err(|err| format!("{}: {}", name, err))?
+        };
+        Ok(shaderc::ResolvedInclude { resolved_name: name.to_string(), content })
+    });
     match compiler.compile_into_spirv(code.as_str(), kind, name, "main", Some(&options)) {
         Ok(artifact) => {
             if artifact.get_num_warnings() != 0 {
                 eprintln!("{}", artifact.get_warning_messages());
             }
-            Some(Box::from(artifact.as_binary_u8()))
+            Ok(Box::from(artifact.as_binary_u8()))
         }
-        Err(err) => {
-            eprintln!("{}", err);
-            None
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn load_scene(path: &std::path::Path, args: &Args) -> std::io::Result<Octree> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("vox") => {
+            let (octree, palette) = vox::load_vox(path, 8.0)?;
+            println!("imported {} with {} palette entries", path.display(), palette.len());
+            Ok(octree)
         }
+        Some("obj") | Some("gltf") | Some("glb") => {
+            let mesh = Mesh::load(path)?;
+            voxelize(&mesh, 8, 8.0, args.solid).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+        }
+        _ => Octree::load(path),
+    }
+}
+
+// A voxel in every corner of a 4x4x4 grid, shallow enough for every encoding.
+fn default_scene() -> Octree {
+    //let octree = Octree::new_wall(12, 8.0);
+    let mut octree = Octree::new(2, 8.0).unwrap();
+    for i in 0..8 {
+        let [x, y, z] = [0, 1, 2].map(|axis| (i >> axis & 1) * 3);
+        octree.set(x, y, z, 2, 1).unwrap();
+    }
+    octree
+}
+
+// The contents of the storage buffer for `octree` in the encoding the shaders expect.
+fn encode_octree(octree: &Octree, args: &Args) -> Result<Vec<u8>, String> {
+    match args.encoding {
+        Encoding::Dag => {
+            let dag = Dag::from_octree(octree)?;
+            println!("dag nodes: {} (octree nodes: {})", dag.data().len(), octree.data().len());
+            Ok(bytemuck::cast_slice(dag.data()).to_vec())
+        }
+        Encoding::Packed => {
+            let words = PackedOctree::from_octree(octree)?.to_words();
+            println!(
+                "packed size: {} bytes (octree size: {} bytes)",
+                words.len() * std::mem::size_of::<u32>(),
+                std::mem::size_of_val(octree.data())
+            );
+            Ok(bytemuck::cast_slice(words.as_slice()).to_vec())
+        }
+        // only the fragment shader follows the ropes, traverse.comp keeps a stack for every encoding
+        Encoding::Octree if args.compute && octree.depth() > ComputeRenderer::MAX_DEPTH => Err(format!(
+            "depth {} is deeper than the {} levels traverse.comp can trace",
+            octree.depth(),
+            ComputeRenderer::MAX_DEPTH
+        )),
+        Encoding::Octree => Ok(bytemuck::cast_slice(octree.data()).to_vec()),
     }
 }
 
 fn create_octree(
     device: &wgpu::Device,
     layout: &wgpu::BindGroupLayout,
+    args: &Args,
 ) -> (Octree, wgpu::Buffer, wgpu::BindGroup) {
-    //let octree = Octree::new_random(8, 8.0, 0.005);
-    //let octree = Octree::new_wall(12, 8.0);
-    let mut octree = Octree {
-        data: vec![
-            Node { material_id: 0, sub_voxels: [1, 2, 3, 4, 5, 6, 7, 8], level: 0, ..Default::default() },
-            Node { material_id: 0, sub_voxels: [9, 0, 0, 0, 0, 0, 0, 0], level: 1, ..Default::default() },
-            Node { material_id: 0, sub_voxels: [0, 10, 0, 0, 0, 0, 0, 0], ..Default::default() },
-            Node { material_id: 0, sub_voxels: [0, 0, 11, 0, 0, 0, 0, 0], ..Default::default() },
-            Node { material_id: 0, sub_voxels: [0, 0, 0, 12, 0, 0, 0, 0], ..Default::default() },
-            Node { material_id: 0, sub_voxels: [0, 0, 0, 0, 13, 0, 0, 0], ..Default::default() },
-            Node { material_id: 0, sub_voxels: [0, 0, 0, 0, 0, 14, 0, 0], ..Default::default() },
-            Node { material_id: 0, sub_voxels: [0, 0, 0, 0, 0, 0, 15, 0], ..Default::default() },
-            Node { material_id: 0, sub_voxels: [0, 0, 0, 0, 0, 0, 0, 16], ..Default::default() },
-            Node { material_id: 1, sub_voxels: [0, 0, 0, 0, 0, 0, 0, 0], ..Default::default() },
-            Node { material_id: 1, sub_voxels: [0, 0, 0, 0, 0, 0, 0, 0], ..Default::default() },
-            Node { material_id: 1, sub_voxels: [0, 0, 0, 0, 0, 0, 0, 0], ..Default::default() },
-            Node { material_id: 1, sub_voxels: [0, 0, 0, 0, 0, 0, 0, 0], ..Default::default() },
-            Node { material_id: 1, sub_voxels: [0, 0, 0, 0, 0, 0, 0, 0], ..Default::default() },
-            Node { material_id: 1, sub_voxels: [0, 0, 0, 0, 0, 0, 0, 0], ..Default::default() },
-            Node { material_id: 1, sub_voxels: [0, 0, 0, 0, 0, 0, 0, 0], ..Default::default() },
-            Node { material_id: 1, sub_voxels: [0, 0, 0, 0, 0, 0, 0, 0], ..Default::default() },
-        ],
-        depth: 2,
-        size: 8.0,
+    // a scene that fails to load falls back to the generator or the default scene
+    let scene = args.scene.as_ref().and_then(|path| match load_scene(path, args) {
+        Ok(octree) => Some(octree),
+        Err(err) => {
+            eprintln!("failed to load {}: {}", path.display(), err);
+            None
+        }
+    });
+    let mut octree = match (scene, &args.generator) {
+        (Some(octree), _) => octree,
+        (None, Some(Generator::Random(seed))) => {
+            Octree::new_random(&GeneratorConfig::new(*seed, 8, 0.005), 8.0).unwrap()
+        }
+        (None, Some(Generator::Heightfield(seed))) => Octree::new_heightfield(8, 8.0, &Noise::new(*seed)).unwrap(),
+        (None, Some(Generator::Density(seed))) => Octree::new_density(8, 8.0, &Noise::new(*seed)).unwrap(),
+        (None, None) => default_scene(),
     };
-    Octree::generate_ropes(&mut octree.data);
-    for node in &octree.data {
+    octree.optimize();
+    for node in octree.data() {
         println!("{:?}", node);
     }
+    // a tree the encoding cannot trace falls back to the default scene as well
+    let contents = encode_octree(&octree, args).unwrap_or_else(|err| {
+        eprintln!("failed to encode the octree: {}", err);
+        octree = default_scene();
+        octree.optimize();
+        encode_octree(&octree, args).expect("Failed to encode the default scene")
+    });
     let octree_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: None,
-        contents: &bytemuck::cast_slice(octree.data.as_slice()),
+        contents: &contents,
         usage: wgpu::BufferUsages::STORAGE,
     });
     let octree_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -100,80 +236,136 @@ fn create_octree(
     (octree, octree_buffer, octree_bind_group)
 }
 
+// Starts outside of the octree looking at its center, where the old orbiting camera started.
+fn create_camera(args: &Args) -> Camera {
+    let angle = std::f32::consts::PI / 4.0;
+    let mut camera = Camera::looking_at(Vector3::new(20.0 * angle.cos(), 20.0 * angle.sin(), 0.0), Vector3::zero());
+    if let Some(speed) = args.speed {
+        camera.speed = speed;
+    }
+    camera
+}
+
+fn camera_uniforms(width: u32, height: u32, octree: &Octree, camera: &Camera) -> Uniforms {
+    let (view_dir, right, up) = camera.basis();
+    Uniforms {
+        view_pos: camera.position.into(),
+        view_dir: view_dir.into(),
+        view_up: up.into(),
+        view_right: right.into(),
+        width: width as i32,
+        height: height as i32,
+        fov: camera.fov,
+        octree_size: octree.size(),
+        octree_depth: octree.depth(),
+    }
+}
+
+// The buffers live as long as the renderer and are updated with `queue.write_buffer`.
 fn create_uniforms(
     device: &wgpu::Device,
     layout: &wgpu::BindGroupLayout,
-    config: &wgpu::SurfaceConfiguration,
-    octree: &Octree,
-    angle: f32,
-) -> (Uniforms, wgpu::Buffer, wgpu::BindGroup) {
-    let uniforms = {
-        let origin = Vector3::<f32>::new(20.0 * angle.cos(), 20.0 * angle.sin(), 0.0);
-        let view_dir = (Vector3::zero() - origin).normalize();
-        let global_up = Vector3::unit_z();
-        let right = view_dir.cross(global_up);
-        let up = right.cross(view_dir);
-        let fov = std::f32::consts::PI * 90.0 / 180.0;
-
-        Uniforms {
-            view_pos: origin.into(),
-            view_dir: view_dir.into(),
-            view_up: up.into(),
-            view_right: right.into(),
-            width: config.width as i32,
-            height: config.height as i32,
-            fov,
-            octree_size: octree.size,
-            octree_depth: octree.depth,
-        }
-    };
-
+    uniforms: &Uniforms,
+    frame: &FrameConstants,
+) -> (wgpu::Buffer, wgpu::Buffer, wgpu::BindGroup) {
     let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: None,
         contents: uniforms.as_std140().as_bytes(),
         usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
     });
 
+    let frame_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: None,
+        contents: frame.as_std140().as_bytes(),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+
     let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
         label: None,
         layout: &layout,
-        entries: &[wgpu::BindGroupEntry {
-            binding: 0,
-            resource: uniform_buffer.as_entire_binding(),
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: frame_buffer.as_entire_binding(),
+            },
+        ],
+    });
+
+    (uniform_buffer, frame_buffer, uniform_bind_group)
+}
+
+fn draw_fullscreen(
+    encoder: &mut wgpu::CommandEncoder,
+    view: &wgpu::TextureView,
+    pipeline: &wgpu::RenderPipeline,
+    bind_groups: &[&wgpu::BindGroup],
+) {
+    let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: None,
+        color_attachments: &[wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations::default(),
         }],
+        depth_stencil_attachment: None,
     });
+    rpass.set_pipeline(pipeline);
+    for (index, bind_group) in bind_groups.iter().enumerate() {
+        rpass.set_bind_group(index as u32, bind_group, &[]);
+    }
+    rpass.draw(0..6, 0..1);
+}
 
-    (uniforms, uniform_buffer, uniform_bind_group)
+fn draw_frame(
+    encoder: &mut wgpu::CommandEncoder,
+    view: &wgpu::TextureView,
+    render_pipeline: &wgpu::RenderPipeline,
+    compute: Option<&ComputeRenderer>,
+    bind_groups: &[&wgpu::BindGroup],
+) {
+    match compute {
+        Some(compute) => compute.render(encoder, view, bind_groups),
+        None => draw_fullscreen(encoder, view, render_pipeline, bind_groups),
+    }
 }
 
-fn reload_shaders(
+fn shader_dir() -> std::path::PathBuf {
+    std::path::PathBuf::from(std::env!("CARGO_MANIFEST_DIR"))
+        .join("res")
+        .join("shaders")
+}
+
+// Runs `create` and returns the validation errors it caused instead of panicking on them.
+fn with_validation<T, F: FnOnce() -> T>(device: &wgpu::Device, create: F) -> Result<T, String> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let result = create();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(err) => Err(err.to_string()),
+        None => Ok(result),
+    }
+}
+
+fn render_pipeline_from_spirv(
     device: &wgpu::Device,
     pipeline_layout: &wgpu::PipelineLayout,
     swapchain_format: wgpu::TextureFormat,
+    vertex_shader_code: &[u8],
+    fragment_shader_code: &[u8],
 ) -> wgpu::RenderPipeline {
-    let shader_dir = std::path::PathBuf::from(std::env!("CARGO_MANIFEST_DIR"))
-        .join("res")
-        .join("shaders");
-    let vertex_shader_code = compile_shader_alternative(
-        &shader_dir,
-        "shader.vert",
-        shaderc::ShaderKind::Vertex,
-    ).unwrap_or(Box::from(include_bytes!(concat!(env!("OUT_DIR"), "/fallback_vert.spv")).as_slice()));
-    let fragment_shader_code = compile_shader_alternative(
-        &shader_dir,
-        "shader.frag",
-        shaderc::ShaderKind::Fragment,
-    ).unwrap_or(Box::from(include_bytes!(concat!(env!("OUT_DIR"), "/fallback_frag.spv")).as_slice()));
     let vertex_shader = unsafe {
         device.create_shader_module_spirv(&wgpu::ShaderModuleDescriptorSpirV {
             label: None,
-            source: bytemuck::cast_slice(vertex_shader_code.as_ref()).into(),
+            source: bytemuck::cast_slice(vertex_shader_code).into(),
         })
     };
     let fragment_shader = unsafe {
         device.create_shader_module_spirv(&wgpu::ShaderModuleDescriptorSpirV {
             label: None,
-            source: bytemuck::cast_slice(fragment_shader_code.as_ref()).into(),
+            source: bytemuck::cast_slice(fragment_shader_code).into(),
         })
     };
     device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
@@ -196,15 +388,93 @@ fn reload_shaders(
     })
 }
 
-async fn run(event_loop: EventLoop<()>, window: Window) {
-    let size = window.inner_size();
-    let instance = wgpu::Instance::new(wgpu::Backends::VULKAN);
-    let surface = unsafe { instance.create_surface(&window) };
+// The blank shaders from build.rs, for when the shaders in res/shaders don't compile.
+fn fallback_render_pipeline(
+    device: &wgpu::Device,
+    pipeline_layout: &wgpu::PipelineLayout,
+    swapchain_format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let vertex_shader_code: Box<[u8]> =
+        Box::from(include_bytes!(concat!(env!("OUT_DIR"), "/fallback_vert.spv")).as_slice());
+    let fragment_shader_code: Box<[u8]> =
+        Box::from(include_bytes!(concat!(env!("OUT_DIR"), "/fallback_frag.spv")).as_slice());
+    render_pipeline_from_spirv(device, pipeline_layout, swapchain_format, &vertex_shader_code, &fragment_shader_code)
+}
+
+// Compiles shader.vert and `fragment_shader_name`, compile errors start with the file and line.
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    pipeline_layout: &wgpu::PipelineLayout,
+    swapchain_format: wgpu::TextureFormat,
+    fragment_shader_name: &str,
+) -> Result<wgpu::RenderPipeline, String> {
+    let shader_dir = shader_dir();
+    let vertex_shader_code = compile_shader_alternative(&shader_dir, "shader.vert", shaderc::ShaderKind::Vertex, &[])?;
+    let fragment_shader_code =
+        compile_shader_alternative(&shader_dir, fragment_shader_name, shaderc::ShaderKind::Fragment, &[])?;
+    with_validation(device, || {
+        render_pipeline_from_spirv(device, pipeline_layout, swapchain_format, &vertex_shader_code, &fragment_shader_code)
+    })
+}
+
+fn reload_shaders(
+    device: &wgpu::Device,
+    pipeline_layout: &wgpu::PipelineLayout,
+    swapchain_format: wgpu::TextureFormat,
+    fragment_shader_name: &str,
+) -> wgpu::RenderPipeline {
+    create_render_pipeline(device, pipeline_layout, swapchain_format, fragment_shader_name).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        fallback_render_pipeline(device, pipeline_layout, swapchain_format)
+    })
+}
+
+// Rebuilds the pipelines from res/shaders, the ones that fail to compile are kept as they are.
+fn reload_pipelines(
+    device: &wgpu::Device,
+    pipeline_layout: &wgpu::PipelineLayout,
+    swapchain_format: wgpu::TextureFormat,
+    fragment_shader_name: &str,
+    render_pipeline: &mut wgpu::RenderPipeline,
+    compute: Option<&mut ComputeRenderer>,
+) -> Result<(), String> {
+    let mut errors = vec![];
+    match create_render_pipeline(device, pipeline_layout, swapchain_format, fragment_shader_name) {
+        Ok(pipeline) => *render_pipeline = pipeline,
+        Err(err) => errors.push(err),
+    }
+    if let Some(Err(err)) = compute.map(|compute| compute.reload(device)) {
+        errors.push(err);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+// Puts the first shader error in the window title, the whole message goes to stderr.
+fn show_shader_status(window: &Window, result: Result<(), String>) {
+    match result {
+        Ok(()) => window.set_title(TITLE),
+        Err(err) => {
+            eprintln!("{}", err);
+            let line = err.lines().find(|line| line.contains("error:")).unwrap_or_else(|| err.lines().next().unwrap_or(""));
+            window.set_title(&format!("{} - {}", TITLE, line.trim()));
+        }
+    }
+}
+
+async fn create_device(
+    instance: &wgpu::Instance,
+    surface: Option<&wgpu::Surface>,
+    force_fallback_adapter: bool,
+) -> (wgpu::Adapter, wgpu::Device, wgpu::Queue) {
     let adapter = instance
         .request_adapter(&wgpu::RequestAdapterOptions {
-            force_fallback_adapter: false,
+            force_fallback_adapter,
             power_preference: wgpu::PowerPreference::HighPerformance,
-            compatible_surface: Some(&surface),
+            compatible_surface: surface,
         })
         .await
         .expect("Failed to find appropriate adapter");
@@ -222,20 +492,35 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
         )
         .await
         .expect("Failed to create device");
+    (adapter, device, queue)
+}
 
+fn create_layouts(device: &wgpu::Device) -> (wgpu::BindGroupLayout, wgpu::BindGroupLayout, wgpu::PipelineLayout) {
     let uniform_bind_group_layout =
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: None,
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
         });
 
     let octree_bind_group_layout =
@@ -243,7 +528,7 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
             label: None,
             entries: &[wgpu::BindGroupLayoutEntry {
                 binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
+                visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Storage { read_only: false },
                     has_dynamic_offset: false,
@@ -258,13 +543,60 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
         bind_group_layouts: &[&uniform_bind_group_layout, &octree_bind_group_layout],
         push_constant_ranges: &[],
     });
+    (uniform_bind_group_layout, octree_bind_group_layout, pipeline_layout)
+}
+
+// Renders a single frame without a window and writes it to `path`, for machines without a
+// display. Combine with --software to render on a CPU adapter.
+async fn run_headless(args: Args, path: &std::path::Path) {
+    let fragment_shader_name = args.encoding.fragment_shader();
+    let (width, height) = (1000, 1000);
+    let instance = wgpu::Instance::new(wgpu::Backends::all());
+    let (_adapter, device, queue) = create_device(&instance, None, args.software).await;
+    let (uniform_bind_group_layout, octree_bind_group_layout, pipeline_layout) = create_layouts(&device);
+    let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+    let render_pipeline = reload_shaders(&device, &pipeline_layout, format, fragment_shader_name);
+    let (octree, _octree_buffer, octree_bind_group) = create_octree(&device, &octree_bind_group_layout, &args);
+    let uniforms = camera_uniforms(width, height, &octree, &create_camera(&args));
+    let (_uniform_buffer, _frame_buffer, uniform_bind_group) =
+        create_uniforms(&device, &uniform_bind_group_layout, &uniforms, &FrameConstants::new(0.0, 0));
+    let compute = args.compute.then(|| {
+        let mut compute = ComputeRenderer::new(
+            &device,
+            &uniform_bind_group_layout,
+            &octree_bind_group_layout,
+            format,
+            width,
+            height,
+            args.encoding.defines(),
+        );
+        if let Err(err) = compute.reload(&device) {
+            eprintln!("{}", err);
+        }
+        compute
+    });
+    let pixels = offscreen::render_to_image(&device, &queue, format, width, height, |encoder, view| {
+        draw_frame(encoder, view, &render_pipeline, compute.as_ref(), &[&uniform_bind_group, &octree_bind_group])
+    });
+    match offscreen::write_png(path, width, height, &pixels) {
+        Ok(()) => println!("saved {}", path.display()),
+        Err(err) => eprintln!("failed to save {}: {}", path.display(), err),
+    }
+}
+
+async fn run(event_loop: EventLoop<()>, window: Window, args: Args) {
+    let fragment_shader_name = args.encoding.fragment_shader();
+    let size = window.inner_size();
+    let instance = wgpu::Instance::new(wgpu::Backends::VULKAN);
+    let surface = unsafe { instance.create_surface(&window) };
+    let (adapter, device, queue) = create_device(&instance, Some(&surface), args.software).await;
+    let (uniform_bind_group_layout, octree_bind_group_layout, pipeline_layout) = create_layouts(&device);
 
     let swapchain_format = surface.get_preferred_format(&adapter).unwrap();
 
-    let mut render_pipeline =
-        reload_shaders(&device, &pipeline_layout, swapchain_format);
+    let mut render_pipeline = fallback_render_pipeline(&device, &pipeline_layout, swapchain_format);
 
-    let config = wgpu::SurfaceConfiguration {
+    let mut config = wgpu::SurfaceConfiguration {
         usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
         format: swapchain_format,
         width: size.width,
@@ -274,14 +606,44 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
 
     surface.configure(&device, &config);
 
+    let mut compute = args.compute.then(|| {
+        ComputeRenderer::new(
+            &device,
+            &uniform_bind_group_layout,
+            &octree_bind_group_layout,
+            swapchain_format,
+            config.width,
+            config.height,
+            args.encoding.defines(),
+        )
+    });
+    show_shader_status(
+        &window,
+        reload_pipelines(
+            &device,
+            &pipeline_layout,
+            swapchain_format,
+            fragment_shader_name,
+            &mut render_pipeline,
+            compute.as_mut(),
+        ),
+    );
+    let shader_watcher = ShaderWatcher::new(&shader_dir())
+        .map_err(|err| eprintln!("not watching the shaders for changes: {}", err))
+        .ok();
+
     let (mut octree, mut octree_buffer, mut octree_bind_group) =
-        create_octree(&device, &octree_bind_group_layout);
+        create_octree(&device, &octree_bind_group_layout, &args);
 
-    let mut angle = std::f32::consts::PI / 4.0;
-    let (mut uniforms, mut uniform_buffer, mut uniform_bind_group) =
-        create_uniforms(&device, &uniform_bind_group_layout, &config, &octree, angle);
+    let mut camera = create_camera(&args);
+    let mut uniforms = camera_uniforms(config.width, config.height, &octree, &camera);
+    let start = Instant::now();
+    let mut frame_index = 0;
+    let (uniform_buffer, frame_buffer, uniform_bind_group) =
+        create_uniforms(&device, &uniform_bind_group_layout, &uniforms, &FrameConstants::new(0.0, frame_index));
 
     let mut now = Instant::now();
+    let mut last_frame = Instant::now();
     let mut count = 0;
 
     event_loop.run(move |event, _, control_flow| {
@@ -289,41 +651,52 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
 
         *control_flow = ControlFlow::Poll;
         match event {
-            // Event::WindowEvent {
-            //     event: WindowEvent::Resized(size),
-            //     ..
-            // } => {
-            //     if size.width != 0 && size.height != 0 {
-            //         config.width = size.width.max(1);
-            //         config.height = size.height.max(1);
-            //         surface.configure(&device, &config);
-            //     }
-            // }
+            // minimized windows report a size of zero, keep the last size until restored
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                ..
+            }
+            | Event::WindowEvent {
+                event: WindowEvent::ScaleFactorChanged { new_inner_size: &mut size, .. },
+                ..
+            } if size.width != 0 && size.height != 0 => {
+                config.width = size.width;
+                config.height = size.height;
+                surface.configure(&device, &config);
+                if let Some(compute) = &mut compute {
+                    compute.resize(&device, config.width, config.height);
+                }
+            }
             Event::RedrawRequested(_) => {
-                let output = surface
-                    .get_current_texture()
-                    .expect("Failed to acquire next swap chain texture");
+                uniforms = camera_uniforms(config.width, config.height, &octree, &camera);
+                queue.write_buffer(&uniform_buffer, 0, uniforms.as_std140().as_bytes());
+                let frame = FrameConstants::new(start.elapsed().as_secs_f32(), frame_index);
+                queue.write_buffer(&frame_buffer, 0, frame.as_std140().as_bytes());
+                frame_index = frame_index.wrapping_add(1);
+
+                let output = match surface.get_current_texture() {
+                    Ok(output) => output,
+                    // the surface no longer matches the window, it is reconfigured and the frame
+                    // is drawn next time
+                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                        surface.configure(&device, &config);
+                        return;
+                    }
+                    Err(err) => panic!("Failed to acquire next swap chain texture: {}", err),
+                };
                 let view = output
                     .texture
                     .create_view(&wgpu::TextureViewDescriptor::default());
 
                 let mut encoder =
                     device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
-                {
-                    let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: None,
-                        color_attachments: &[wgpu::RenderPassColorAttachment {
-                            view: &view,
-                            resolve_target: None,
-                            ops: wgpu::Operations::default(),
-                        }],
-                        depth_stencil_attachment: None,
-                    });
-                    rpass.set_pipeline(&render_pipeline);
-                    rpass.set_bind_group(0, &uniform_bind_group, &[]);
-                    rpass.set_bind_group(1, &octree_bind_group, &[]);
-                    rpass.draw(0..6, 0..1);
-                }
+                draw_frame(
+                    &mut encoder,
+                    &view,
+                    &render_pipeline,
+                    compute.as_ref(),
+                    &[&uniform_bind_group, &octree_bind_group],
+                );
                 queue.submit(Some(encoder.finish()));
                 output.present();
 
@@ -335,8 +708,38 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
                 }
             }
             Event::MainEventsCleared => {
+                let dt = last_frame.elapsed().as_secs_f32();
+                last_frame = Instant::now();
+                camera.update(dt);
+                if matches!(&shader_watcher, Some(watcher) if watcher.changed()) {
+                    show_shader_status(
+                        &window,
+                        reload_pipelines(
+                            &device,
+                            &pipeline_layout,
+                            swapchain_format,
+                            fragment_shader_name,
+                            &mut render_pipeline,
+                            compute.as_mut(),
+                        ),
+                    );
+                }
                 window.request_redraw();
             }
+            Event::WindowEvent {
+                event: WindowEvent::MouseInput { button, state, .. },
+                ..
+            } => camera.process_button(button, state),
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta: (dx, dy) },
+                ..
+            } => {
+                camera.process_mouse(dx, dy);
+            }
+            Event::DeviceEvent {
+                event: DeviceEvent::Key(KeyboardInput { state, virtual_keycode: Some(keycode), .. }),
+                ..
+            } if Camera::is_movement_key(keycode) => camera.process_key(keycode, state),
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
@@ -350,48 +753,65 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
                                  }),
                 ..
             } => match keycode {
-                VirtualKeyCode::R => {
-                    render_pipeline = reload_shaders(
+                VirtualKeyCode::R => show_shader_status(
+                    &window,
+                    reload_pipelines(
                         &device,
                         &pipeline_layout,
                         swapchain_format,
-                    );
-                }
+                        fragment_shader_name,
+                        &mut render_pipeline,
+                        compute.as_mut(),
+                    ),
+                ),
                 VirtualKeyCode::B => {
                     (octree, octree_buffer, octree_bind_group) =
-                        create_octree(&device, &octree_bind_group_layout);
-                }
-                VirtualKeyCode::A | VirtualKeyCode::Left => {
-                    angle += 3.0 * std::f32::consts::PI / 180.0;
-                    (uniforms, uniform_buffer, uniform_bind_group) = create_uniforms(
-                        &device,
-                        &uniform_bind_group_layout,
-                        &config,
-                        &octree,
-                        angle,
-                    );
+                        create_octree(&device, &octree_bind_group_layout, &args);
                 }
-                VirtualKeyCode::D | VirtualKeyCode::Right => {
-                    angle -= 3.0 * std::f32::consts::PI / 180.0;
-                    (uniforms, uniform_buffer, uniform_bind_group) = create_uniforms(
+                VirtualKeyCode::F12 => {
+                    let pixels = offscreen::render_to_image(
                         &device,
-                        &uniform_bind_group_layout,
-                        &config,
-                        &octree,
-                        angle,
+                        &queue,
+                        swapchain_format,
+                        config.width,
+                        config.height,
+                        |encoder, view| {
+                            draw_frame(
+                                encoder,
+                                view,
+                                &render_pipeline,
+                                compute.as_ref(),
+                                &[&uniform_bind_group, &octree_bind_group],
+                            )
+                        },
                     );
+                    let timestamp = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis();
+                    let name = format!("screenshot-{}", timestamp);
+                    let result = offscreen::write_png(format!("{}.png", name), config.width, config.height, &pixels)
+                        .and_then(|()| std::fs::write(format!("{}.json", name), uniforms.to_json()));
+                    match result {
+                        Ok(()) => println!("saved {}.png and {}.json", name, name),
+                        Err(err) => eprintln!("failed to save screenshot {}: {}", name, err),
+                    }
                 }
+                VirtualKeyCode::F5 => match octree.save("scene.svo") {
+                    Ok(()) => println!("saved scene.svo"),
+                    Err(err) => eprintln!("failed to save scene.svo: {}", err),
+                },
                 VirtualKeyCode::M => {
-                    for (index, node) in octree.data.iter().enumerate() {
-                        print!("Node({}, int[](", node.material_id);
-                        for (index, sub_voxel) in node.sub_voxels.iter().enumerate() {
+                    for (index, node) in octree.data().iter().enumerate() {
+                        print!("Node({}, int[](", node.material_id());
+                        for (index, sub_voxel) in node.sub_voxels().iter().enumerate() {
                             print!("{}", *sub_voxel);
                             if index != 7 {
                                 print!(", ");
                             }
                         }
                         print!("))");
-                        if index != octree.data.len() - 1 {
+                        if index != octree.data().len() - 1 {
                             println!(",");
                         }
                     }
@@ -405,11 +825,16 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
 
 fn main() {
     env_logger::init();
+    let args = Args::parse();
+    if let Some(path) = args.headless.clone() {
+        pollster::block_on(run_headless(args, &path));
+        return;
+    }
     let event_loop = EventLoop::new();
     let window = winit::window::WindowBuilder::new()
-        .with_resizable(false)
+        .with_title(TITLE)
         .with_inner_size(winit::dpi::PhysicalSize::new(1000, 1000))
         .build(&event_loop)
         .unwrap();
-    pollster::block_on(run(event_loop, window));
+    pollster::block_on(run(event_loop, window, args));
 }
@@ -0,0 +1,131 @@
+// Seeded fractal value noise. Lattice values come from hashing the integer coordinates with
+// the seed, so the same seed gives the same terrain on every machine.
+#[derive(Clone, Copy, Debug)]
+pub struct Noise {
+    pub seed: u64,
+    pub octaves: u32,
+    pub frequency: f32,
+    pub lacunarity: f32,
+    pub persistence: f32,
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, octaves: 5, frequency: 2.0, lacunarity: 2.0, persistence: 0.5 }
+    }
+
+    pub fn get2(&self, x: f32, y: f32) -> f32 {
+        self.get3(x, y, 0.0)
+    }
+
+    // Returns a value in [-1, 1].
+    pub fn get3(&self, x: f32, y: f32, z: f32) -> f32 {
+        let mut sum = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = self.frequency;
+        for octave in 0..self.octaves {
+            let seed = self.seed.wrapping_add(octave as u64);
+            sum += amplitude * value(seed, x * frequency, y * frequency, z * frequency);
+            amplitude *= self.persistence;
+            frequency *= self.lacunarity;
+        }
+        sum / self.total_amplitude()
+    }
+
+    // Upper bound for how fast `get3` changes per unit of distance.
+    pub fn lipschitz(&self) -> f32 {
+        // a lattice cell interpolates values at most 2 apart and the fade curve has a slope of
+        // at most 15/8, per axis
+        let octave_bound = 2.0 * 15.0 / 8.0 * 3f32.sqrt();
+        let mut bound = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = self.frequency;
+        for _ in 0..self.octaves {
+            bound += amplitude * frequency * octave_bound;
+            amplitude *= self.persistence;
+            frequency *= self.lacunarity;
+        }
+        bound / self.total_amplitude()
+    }
+
+    fn total_amplitude(&self) -> f32 {
+        (0..self.octaves).map(|octave| self.persistence.powi(octave as i32)).sum::<f32>().max(f32::EPSILON)
+    }
+}
+
+fn hash(seed: u64, x: i32, y: i32, z: i32) -> f32 {
+    let mut h = seed
+        ^ (x as u32 as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (y as u32 as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+        ^ (z as u32 as u64).wrapping_mul(0x1656_67b1_9e37_79f9);
+    // splitmix64 finalizer
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^= h >> 31;
+    (h >> 40) as f32 / (1u64 << 24) as f32 * 2.0 - 1.0
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn value(seed: u64, x: f32, y: f32, z: f32) -> f32 {
+    let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+    let (tx, ty, tz) = (fade(x - x0), fade(y - y0), fade(z - z0));
+    let (x0, y0, z0) = (x0 as i32, y0 as i32, z0 as i32);
+    let corner = |dx: i32, dy: i32, dz: i32| hash(seed, x0 + dx, y0 + dy, z0 + dz);
+    lerp(
+        lerp(
+            lerp(corner(0, 0, 0), corner(1, 0, 0), tx),
+            lerp(corner(0, 1, 0), corner(1, 1, 0), tx),
+            ty,
+        ),
+        lerp(
+            lerp(corner(0, 0, 1), corner(1, 0, 1), tx),
+            lerp(corner(0, 1, 1), corner(1, 1, 1), tx),
+            ty,
+        ),
+        tz,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::Noise;
+
+    #[test]
+    fn same_seed_gives_the_same_values() {
+        let mut rng = StdRng::seed_from_u64(8);
+        let (first, second, other) = (Noise::new(3), Noise::new(3), Noise::new(4));
+        let mut differs = false;
+        for _ in 0..1000 {
+            let [x, y, z] = [0; 3].map(|_| rng.gen_range(-4.0..4.0));
+            assert_eq!(first.get3(x, y, z).to_bits(), second.get3(x, y, z).to_bits());
+            differs |= first.get3(x, y, z) != other.get3(x, y, z);
+        }
+        assert!(differs);
+    }
+
+    #[test]
+    fn values_stay_in_range_and_within_the_lipschitz_bound() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let noise = Noise::new(5);
+        let lipschitz = noise.lipschitz();
+        for _ in 0..10000 {
+            let [x, y, z] = [0; 3].map(|_| rng.gen_range(-4.0..4.0));
+            let [dx, dy, dz] = [0; 3].map(|_| rng.gen_range(-0.01..0.01));
+            let (a, b) = (noise.get3(x, y, z), noise.get3(x + dx, y + dy, z + dz));
+            assert!((-1.0..=1.0).contains(&a), "{} at {:?}", a, [x, y, z]);
+            let distance = (dx * dx + dy * dy + dz * dz).sqrt();
+            // a little slack for rounding
+            assert!((a - b).abs() <= lipschitz * distance + 1e-5, "{} to {} at {:?}", a, b, [x, y, z]);
+        }
+    }
+}
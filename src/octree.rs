@@ -1,15 +1,19 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 use bytemuck::{Zeroable, Pod};
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 pub struct Octree {
     pub(crate) data: Vec<Node>,
     pub(crate) depth: i32,
     pub(crate) size: f32,
+    // slots in `data` released by edits, reused before `data` grows
+    pub(crate) free: Vec<i32>,
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Zeroable, Pod)]
 pub struct Node {
     pub(crate) material_id: i32,
     pub(crate) level: i32,
@@ -17,16 +21,100 @@ pub struct Node {
     pub(crate) ropes: [i32; 6],
 }
 
+// Everything `Octree::new_random` needs to generate the same tree again.
+#[derive(Clone, Debug)]
+pub struct GeneratorConfig {
+    pub seed: u64,
+    pub max_depth: i32,
+    // chance for a node to become a solid leaf, indexed by level, levels past the end stay empty
+    pub fill_chance: Vec<f64>,
+}
+
+impl GeneratorConfig {
+    // Only voxels at `max_depth` are filled.
+    pub fn new(seed: u64, max_depth: i32, chance: f64) -> Self {
+        let fill_chance = (0..=max_depth).map(|level| if level == max_depth { chance } else { 0.0 }).collect();
+        Self { seed, max_depth, fill_chance }
+    }
+}
+
+// Why an edit or a new tree was rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditError {
+    // outside of 0..=Octree::MAX_DEPTH
+    InvalidDepth(i32),
+    // the voxel is outside of the 2^depth grid
+    OutOfBounds { coords: [i32; 3], depth: i32 },
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditError::InvalidDepth(depth) => write!(f, "depth {} is outside of 0..={}", depth, Octree::MAX_DEPTH),
+            EditError::OutOfBounds { coords, depth } => {
+                write!(f, "voxel {:?} is outside of the octree at depth {}", coords, depth)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+impl Node {
+    pub fn is_leaf(&self) -> bool {
+        self.sub_voxels.iter().all(|&sub_voxel| sub_voxel == 0)
+    }
+
+    pub fn material_id(&self) -> i32 {
+        self.material_id
+    }
+
+    pub fn sub_voxels(&self) -> [i32; 8] {
+        self.sub_voxels
+    }
+}
+
 impl Octree {
-    pub fn new_random(depth: i32, size: f32, chance: f64) -> Self {
+    pub fn new(depth: i32, size: f32) -> Result<Self, EditError> {
+        Self::check_depth(depth)?;
+        Ok(Self { data: vec![Node::default()], depth, size, free: vec![] })
+    }
+
+    // The nodes as uploaded to the GPU, including slots on the free list.
+    pub fn data(&self) -> &[Node] {
+        &self.data
+    }
+
+    pub fn depth(&self) -> i32 {
+        self.depth
+    }
+
+    pub fn size(&self) -> f32 {
+        self.size
+    }
+
+    pub fn new_random(config: &GeneratorConfig, size: f32) -> Result<Self, EditError> {
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        Self::new_random_with_rng(config.max_depth, &config.fill_chance, size, &mut rng)
+    }
+
+    // Like `new_random`, drawing from `rng` instead of a generator seeded from the config.
+    pub fn new_random_with_rng<R: Rng>(
+        max_depth: i32,
+        fill_chance: &[f64],
+        size: f32,
+        rng: &mut R,
+    ) -> Result<Self, EditError> {
+        Self::check_depth(max_depth)?;
         let mut data = vec![];
-        Self::new_random_internal(depth, &mut data, chance);
-        Self { data, depth, size }
+        Self::new_random_internal(0, max_depth, fill_chance, &mut data, rng);
+        Ok(Self { data, depth: max_depth, size, free: vec![] })
     }
 
-    fn new_random_internal(depth: i32, data: &mut Vec<Node>, chance: f64) {
+    fn new_random_internal<R: Rng>(level: i32, max_depth: i32, fill_chance: &[f64], data: &mut Vec<Node>, rng: &mut R) {
+        let chance = fill_chance.get(level as usize).copied().unwrap_or(0.0);
         data.push(Node {
-            material_id: if depth == 0 && rand::thread_rng().gen_bool(chance) {
+            material_id: if chance > 0.0 && rng.gen_bool(chance) {
                 Self::SOLID
             } else {
                 Self::EMPTY
@@ -34,11 +122,11 @@ impl Octree {
             ..Default::default()
         });
         let first_address = data.len();
-        if data[first_address - 1].material_id & Self::SOLID == 0 && depth != 0 {
+        if data[first_address - 1].material_id & Self::SOLID == 0 && level != max_depth {
             for i in 0..8 {
                 let new_address = data.len();
                 data[first_address - 1].sub_voxels[i] = new_address as i32;
-                Self::new_random_internal(depth - 1, data, chance);
+                Self::new_random_internal(level + 1, max_depth, fill_chance, data, rng);
             }
         }
     }
@@ -46,7 +134,7 @@ impl Octree {
     pub fn new_wall(depth: i32, size: f32) -> Self {
         let mut data = vec![];
         Self::new_wall_internal(depth, &mut data);
-        Self { data, depth, size }
+        Self { data, depth, size, free: vec![] }
     }
 
 
@@ -71,11 +159,257 @@ impl Octree {
         }
     }
 
+    // Sets the voxel at (x, y, z) in the 2^depth grid, `depth` can be deeper than the tree.
+    pub fn set(&mut self, x: i32, y: i32, z: i32, depth: i32, material_id: i32) -> Result<(), EditError> {
+        if let Some(level) = self.set_voxel_internal(x, y, z, depth, material_id)? {
+            let shift = depth - level;
+            self.update_ropes([x >> shift, y >> shift, z >> shift], level);
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self, x: i32, y: i32, z: i32, depth: i32) -> Result<(), EditError> {
+        self.set(x, y, z, depth, Self::EMPTY)
+    }
+
+    // Like `set` but leaves the ropes alone, for callers that edit many voxels and generate the
+    // ropes once at the end. Returns whether the tree changed.
+    pub(crate) fn set_voxel(&mut self, x: i32, y: i32, z: i32, depth: i32, material_id: i32) -> Result<bool, EditError> {
+        Ok(self.set_voxel_internal(x, y, z, depth, material_id)?.is_some())
+    }
+
+    pub(crate) fn check_depth(depth: i32) -> Result<(), EditError> {
+        if (0..=Self::MAX_DEPTH).contains(&depth) {
+            Ok(())
+        } else {
+            Err(EditError::InvalidDepth(depth))
+        }
+    }
+
+    // Returns the level of the highest node whose subtree changed, or None if nothing changed.
+    fn set_voxel_internal(&mut self, x: i32, y: i32, z: i32, depth: i32, material_id: i32) -> Result<Option<i32>, EditError> {
+        Self::check_depth(depth)?;
+        let extent = 1 << depth;
+        if ![x, y, z].iter().all(|coord| (0..extent).contains(coord)) {
+            return Err(EditError::OutOfBounds { coords: [x, y, z], depth });
+        }
+
+        let mut path = vec![0];
+        let mut current = 0;
+        let mut changed = depth;
+        for level in 0..depth {
+            let node = self.data[current as usize];
+            if node.is_leaf() {
+                if node.material_id == material_id {
+                    // the whole cell already has this material
+                    return Ok(None);
+                }
+                self.subdivide(current);
+                changed = changed.min(level);
+            }
+            let shift = depth - level - 1;
+            let index = ((x >> shift) & 1) | ((y >> shift) & 1) << 1 | ((z >> shift) & 1) << 2;
+            let mut sub_voxel = self.data[current as usize].sub_voxels[index as usize];
+            if sub_voxel == 0 {
+                if material_id == Self::EMPTY {
+                    return Ok(None);
+                }
+                sub_voxel = self.allocate(Node { level: level + 1, ..Default::default() });
+                self.data[current as usize].sub_voxels[index as usize] = sub_voxel;
+                changed = changed.min(level);
+            }
+            current = sub_voxel;
+            path.push(current);
+        }
+        self.depth = self.depth.max(depth);
+
+        self.release_sub_voxels(current);
+        self.data[current as usize].material_id = material_id;
+        path.pop();
+        while let Some(index) = path.pop() {
+            changed = changed.min(path.len() as i32);
+            if !self.collapse(index) {
+                break;
+            }
+        }
+        Ok(Some(changed))
+    }
+
+    // Regenerates the ropes of the subtree at `coords` on `level` after an edit inside it, and
+    // the ropes of the nodes next to it that can point into it. All other ropes point to nodes
+    // at or above `level`, which the edit did not touch.
+    pub(crate) fn update_ropes(&mut self, coords: [i32; 3], level: i32) {
+        if level == 0 {
+            Self::generate_ropes(&mut self.data);
+            return;
+        }
+        let mut stack = VecDeque::from([(0, 0)]);
+        let mut current = 0;
+        for shift in (0..level).rev() {
+            let index = ((coords[0] >> shift) & 1) | ((coords[1] >> shift) & 1) << 1 | ((coords[2] >> shift) & 1) << 2;
+            current = self.data[current as usize].sub_voxels[index as usize];
+            stack.push_back((current, index));
+        }
+        Self::generate_ropes_internal(current, &mut self.data, &mut stack);
+
+        for dir in 0..6 {
+            let neighbor = self.data[current as usize].ropes[dir];
+            if neighbor != -1 && self.data[neighbor as usize].level == level {
+                let mut neighbor_coords = coords;
+                neighbor_coords[dir / 2] += if dir % 2 == 1 { 1 } else { -1 };
+                self.update_face_ropes(neighbor, level, neighbor_coords, dir ^ 1);
+            }
+        }
+    }
+
+    // Regenerates the `dir` ropes of the nodes in the subtree of `index` that touch the face of
+    // its cell in direction `dir`.
+    fn update_face_ropes(&mut self, index: i32, level: i32, coords: [i32; 3], dir: usize) {
+        self.data[index as usize].ropes[dir] = self.find_rope(level, coords, dir);
+        let axis = dir / 2;
+        for i in 0..8 {
+            let sub_voxel = self.data[index as usize].sub_voxels[i];
+            if sub_voxel != 0 && (i >> axis) & 1 == dir % 2 {
+                let child_coords = [0, 1, 2].map(|axis| coords[axis] * 2 + ((i as i32 >> axis) & 1));
+                self.update_face_ropes(sub_voxel, level + 1, child_coords, dir);
+            }
+        }
+    }
+
+    // The deepest node at or above `level` containing the cell next to `coords` in direction
+    // `dir`, or -1 if that cell is outside of the tree.
+    fn find_rope(&self, level: i32, coords: [i32; 3], dir: usize) -> i32 {
+        let mut neighbor = coords;
+        neighbor[dir / 2] += if dir % 2 == 1 { 1 } else { -1 };
+        if neighbor[dir / 2] < 0 || neighbor[dir / 2] >= 1 << level {
+            return -1;
+        }
+        let mut current = 0;
+        for shift in (0..level).rev() {
+            let index = ((neighbor[0] >> shift) & 1) | ((neighbor[1] >> shift) & 1) << 1 | ((neighbor[2] >> shift) & 1) << 2;
+            let sub_voxel = self.data[current as usize].sub_voxels[index as usize];
+            if sub_voxel == 0 {
+                break;
+            }
+            current = sub_voxel;
+        }
+        current
+    }
+
+    pub(crate) fn allocate(&mut self, node: Node) -> i32 {
+        match self.free.pop() {
+            Some(index) => {
+                self.data[index as usize] = node;
+                index
+            }
+            None => {
+                self.data.push(node);
+                self.data.len() as i32 - 1
+            }
+        }
+    }
+
+    fn release(&mut self, index: i32) {
+        self.release_sub_voxels(index);
+        self.data[index as usize] = Node::default();
+        self.free.push(index);
+    }
+
+    pub(crate) fn release_sub_voxels(&mut self, index: i32) {
+        for sub_voxel in self.data[index as usize].sub_voxels {
+            if sub_voxel != 0 {
+                self.release(sub_voxel);
+            }
+        }
+        self.data[index as usize].sub_voxels = [0; 8];
+    }
+
+    // Turns a leaf into an inner node whose sub voxels all have the leaf's material. Empty
+    // leaves need no sub voxels since empty octants are not stored.
+    pub(crate) fn subdivide(&mut self, index: i32) {
+        let node = self.data[index as usize];
+        if node.material_id == Self::EMPTY {
+            return;
+        }
+        for i in 0..8 {
+            let sub_voxel = self.allocate(Node {
+                material_id: node.material_id,
+                level: node.level + 1,
+                ..Default::default()
+            });
+            self.data[index as usize].sub_voxels[i] = sub_voxel;
+        }
+        self.data[index as usize].material_id = Self::EMPTY;
+    }
+
+    // Drops empty leaf sub voxels and merges eight leaves of the same material into their
+    // parent. Returns whether the node ended up as a leaf.
+    pub(crate) fn collapse(&mut self, index: i32) -> bool {
+        for i in 0..8 {
+            let sub_voxel = self.data[index as usize].sub_voxels[i];
+            if sub_voxel != 0 {
+                let child = self.data[sub_voxel as usize];
+                if child.is_leaf() && child.material_id == Self::EMPTY {
+                    self.data[index as usize].sub_voxels[i] = 0;
+                    self.release(sub_voxel);
+                }
+            }
+        }
+        let node = self.data[index as usize];
+        if node.is_leaf() {
+            return true;
+        }
+        let material_id = self.data[node.sub_voxels[0] as usize].material_id;
+        let mergeable = node.sub_voxels.iter().all(|&sub_voxel| {
+            sub_voxel != 0 && {
+                let child = &self.data[sub_voxel as usize];
+                child.is_leaf() && child.material_id == material_id
+            }
+        });
+        if mergeable {
+            self.release_sub_voxels(index);
+            self.data[index as usize].material_id = material_id;
+        }
+        mergeable
+    }
+
+    pub fn optimize(&mut self) {
+        self.optimize_internal(0);
+        let mut data = Vec::with_capacity(self.data.len() - self.free.len());
+        Self::compact(&self.data, 0, &mut data);
+        self.data = data;
+        self.free.clear();
+        Self::generate_ropes(&mut self.data);
+    }
+
+    fn optimize_internal(&mut self, index: i32) {
+        for sub_voxel in self.data[index as usize].sub_voxels {
+            if sub_voxel != 0 {
+                self.optimize_internal(sub_voxel);
+            }
+        }
+        self.collapse(index);
+    }
+
+    // Copies the nodes reachable from `index` in depth first order, the same layout the
+    // generators produce, and returns the new index of `index`.
+    fn compact(data: &[Node], index: i32, compacted: &mut Vec<Node>) -> i32 {
+        let new_index = compacted.len();
+        compacted.push(data[index as usize]);
+        for (i, sub_voxel) in data[index as usize].sub_voxels.iter().enumerate() {
+            if *sub_voxel != 0 {
+                compacted[new_index].sub_voxels[i] = Self::compact(data, *sub_voxel, compacted);
+            }
+        }
+        new_index as i32
+    }
+
     pub fn generate_ropes(data: &mut Vec<Node>) {
         Self::generate_ropes_internal(0, data, &mut VecDeque::from([(0, 0)]));
     }
 
     fn generate_ropes_internal(root: i32, data: &mut Vec<Node>, stack: &mut VecDeque<(i32, i32)>) {
+        data[root as usize].level = stack.len() as i32 - 1;
         for i in 0..6 {
             let voxel_in_dir = Self::generate_rope(stack, data, i);
             data[root as usize].ropes[i as usize] = voxel_in_dir;
@@ -107,7 +441,12 @@ impl Octree {
             if (subvoxel_dir & subvoxel_index != 0 && !positive_dir) || (subvoxel_dir & subvoxel_index == 0 && positive_dir) {
                 let mut current_voxel_index = voxel_index;
                 for j in total_path_len - i-1..total_path_len - 1 {
-                    current_voxel_index = data[current_voxel_index as usize].sub_voxels[(path[j + 1].1 ^ subvoxel_dir) as usize]
+                    let sub_voxel = data[current_voxel_index as usize].sub_voxels[(path[j + 1].1 ^ subvoxel_dir) as usize];
+                    // the neighbor is not subdivided this far, the rope points to the coarser node
+                    if sub_voxel == 0 {
+                        break;
+                    }
+                    current_voxel_index = sub_voxel;
                 }
                 return current_voxel_index;
             }
@@ -115,6 +454,274 @@ impl Octree {
         -1
     }
 
-    const SOLID: i32 = 1;
-    const EMPTY: i32 = 0;
-}
\ No newline at end of file
+    // Checks that every node reachable from the root is referenced once and not deeper than
+    // `depth`, and that its level and ropes match a brute force search for the neighboring
+    // cells: the same level neighbor if it exists, otherwise the deepest node containing it, or
+    // -1 outside the tree. Returns a description of the first problem found.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut cells = HashMap::new();
+        let mut stack = vec![(0, 0, [0; 3])];
+        while let Some((index, level, coords)) = stack.pop() {
+            if index < 0 || index as usize >= self.data.len() {
+                return Err(format!("sub voxel {} is out of bounds", index));
+            }
+            if level > self.depth {
+                return Err(format!("node {} is deeper than depth {}", index, self.depth));
+            }
+            cells.insert((level, coords), index);
+            if cells.len() > self.data.len() {
+                return Err(format!("node {} is referenced more than once", index));
+            }
+            for (i, &sub_voxel) in self.data[index as usize].sub_voxels.iter().enumerate() {
+                if sub_voxel != 0 {
+                    let child_coords = [0, 1, 2].map(|axis| coords[axis] * 2 + ((i as i32 >> axis) & 1));
+                    stack.push((sub_voxel, level + 1, child_coords));
+                }
+            }
+        }
+        let mut seen = HashSet::new();
+        if cells.values().any(|&index| !seen.insert(index)) {
+            return Err("a node is referenced more than once".to_string());
+        }
+
+        for (&(level, coords), &index) in &cells {
+            let node = &self.data[index as usize];
+            if node.level != level {
+                return Err(format!("node {} has level {}, expected {}", index, node.level, level));
+            }
+            for dir in 0..6 {
+                let axis = dir / 2;
+                let mut neighbor = coords;
+                neighbor[axis] += if dir % 2 == 1 { 1 } else { -1 };
+                let expected = if neighbor[axis] < 0 || neighbor[axis] >= 1 << level {
+                    -1
+                } else {
+                    (0..=level)
+                        .rev()
+                        .find_map(|k| cells.get(&(k, neighbor.map(|coord| coord >> (level - k)))))
+                        .copied()
+                        .unwrap()
+                };
+                if node.ropes[dir] != expected {
+                    return Err(format!(
+                        "node {} at level {} {:?} has rope {} pointing to {}, expected {}",
+                        index, level, coords, dir, node.ropes[dir], expected
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub const SOLID: i32 = 1;
+    pub const EMPTY: i32 = 0;
+    // deepest level whose voxel coordinates still fit an i32
+    pub const MAX_DEPTH: i32 = 30;
+}
+// Fixtures the tests of the other modules share as well.
+#[cfg(test)]
+pub(crate) mod tests {
+    use cgmath::Vector3;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::{EditError, GeneratorConfig, Octree};
+
+    // A random tree of the given depth with ropes, edited at its deepest level so that it also
+    // has released slots and leaves of every material.
+    pub(crate) fn random_octree(rng: &mut StdRng, depth: i32) -> Octree {
+        let fill_chance = (0..=depth).map(|_| rng.gen_range(0.0..0.4)).collect::<Vec<_>>();
+        let mut octree = Octree::new_random_with_rng(depth, &fill_chance, 8.0, rng).unwrap();
+        octree.optimize();
+        for _ in 0..50 {
+            let [x, y, z] = [0; 3].map(|_| rng.gen_range(0..1 << depth));
+            octree.set(x, y, z, depth, rng.gen_range(0..4)).unwrap();
+        }
+        octree
+    }
+
+    // Index of the sub voxel containing `coords`, which are `shift` levels below the sub voxel
+    pub(crate) fn child_index(coords: [i32; 3], shift: i32) -> usize {
+        (0..3).map(|axis| ((coords[axis] >> shift) & 1) << axis).sum::<i32>() as usize
+    }
+
+    // Material of the voxel at `coords` in the 2^depth grid
+    pub(crate) fn voxel(octree: &Octree, depth: i32, coords: [i32; 3]) -> i32 {
+        let cell_size = 2.0 * octree.size / (1 << depth) as f32;
+        let center = coords.map(|coord| -octree.size + (coord as f32 + 0.5) * cell_size);
+        octree.get(Vector3::from(center)).unwrap().material_id
+    }
+
+    // Checks every voxel of the 2^depth grid against `expected`
+    pub(crate) fn assert_voxels(octree: &Octree, depth: i32, expected: impl Fn([i32; 3]) -> i32) {
+        let n = 1 << depth;
+        for i in 0..n * n * n {
+            let coords = [i % n, i / n % n, i / (n * n)];
+            assert_eq!(voxel(octree, depth, coords), expected(coords), "voxel {:?}", coords);
+        }
+    }
+
+    // Checks that the levels and ropes are the ones a full rebuild gives
+    pub(crate) fn assert_ropes_rebuilt(octree: &Octree) {
+        let mut expected = octree.data.clone();
+        Octree::generate_ropes(&mut expected);
+        for (index, (node, expected)) in octree.data.iter().zip(&expected).enumerate() {
+            assert_eq!((node.level, node.ropes), (expected.level, expected.ropes), "node {}", index);
+        }
+    }
+
+    // Sets or clears a random voxel, sometimes coarser or one level deeper than the tree
+    fn random_edit(octree: &mut Octree, rng: &mut StdRng) {
+        let depth = rng.gen_range(0..=octree.depth + 1);
+        let [x, y, z] = [0; 3].map(|_| rng.gen_range(0..1 << depth));
+        if rng.gen_bool(0.4) {
+            octree.clear(x, y, z, depth).unwrap();
+        } else {
+            octree.set(x, y, z, depth, rng.gen_range(1..4)).unwrap();
+        }
+    }
+
+    // Index of the node covering the cell at `coords` on `level`
+    fn node_at(octree: &Octree, level: i32, coords: [i32; 3]) -> i32 {
+        let mut node = 0;
+        for shift in (0..level).rev() {
+            node = octree.data[node as usize].sub_voxels[child_index(coords, shift)];
+            assert_ne!(node, 0, "no node at level {} {:?}", level, coords);
+        }
+        node
+    }
+
+    #[test]
+    fn random_trees_are_valid() {
+        let mut rng = StdRng::seed_from_u64(10);
+        for _ in 0..20 {
+            let depth = rng.gen_range(1..=5);
+            let mut octree = random_octree(&mut rng, depth);
+            assert_eq!(octree.validate(), Ok(()));
+            for _ in 0..100 {
+                random_edit(&mut octree, &mut rng);
+                assert_eq!(octree.validate(), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn edits_update_ropes_like_a_full_rebuild() {
+        let mut rng = StdRng::seed_from_u64(12);
+        for _ in 0..50 {
+            let depth = rng.gen_range(1..=5);
+            let mut octree = random_octree(&mut rng, depth);
+            for step in 0..100 {
+                random_edit(&mut octree, &mut rng);
+                let mut expected = octree.data.clone();
+                Octree::generate_ropes(&mut expected);
+                for (index, (node, expected)) in octree.data.iter().zip(&expected).enumerate() {
+                    let (actual, expected) = ((node.level, node.ropes), (expected.level, expected.ropes));
+                    assert_eq!(actual, expected, "node {} after {} edits", index, step + 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ropes_point_outside_or_to_coarser_neighbors() {
+        let mut octree = Octree::new(2, 8.0).unwrap();
+        octree.set(0, 0, 0, 2, 1).unwrap();
+        octree.set(1, 0, 0, 2, 3).unwrap();
+        octree.set(1, 0, 0, 1, 2).unwrap();
+        assert_eq!(octree.validate(), Ok(()));
+
+        let [first, second] = [[0, 0, 0], [1, 0, 0]].map(|coords| node_at(&octree, 2, coords));
+        let [parent, sibling] = [[0, 0, 0], [1, 0, 0]].map(|coords| node_at(&octree, 1, coords));
+        // outside of the tree
+        assert_eq!(octree.data[first as usize].ropes[0], -1);
+        assert_eq!(octree.data[first as usize].ropes[2], -1);
+        // same level
+        assert_eq!(octree.data[first as usize].ropes[1], second);
+        assert_eq!(octree.data[sibling as usize].ropes[0], parent);
+        // a leaf one level up
+        assert_eq!(octree.data[second as usize].ropes[1], sibling);
+        // empty octants without a node, the rope goes to the node containing them
+        assert_eq!(octree.data[first as usize].ropes[3], parent);
+        assert_eq!(octree.data[second as usize].ropes[5], parent);
+        assert_eq!(octree.data[sibling as usize].ropes[3], 0);
+    }
+
+    #[test]
+    fn detects_broken_trees() {
+        let mut octree = Octree::new_wall(3, 8.0);
+        octree.optimize();
+        assert_eq!(octree.validate(), Ok(()));
+        let leaf = (1..octree.data.len()).find(|&index| octree.data[index].is_leaf()).unwrap();
+
+        let mut broken = octree.data.clone();
+        broken[leaf].ropes[1] = if broken[leaf].ropes[1] == -1 { 0 } else { -1 };
+        let broken = Octree { data: broken, ..Octree::new(octree.depth, octree.size).unwrap() };
+        assert!(broken.validate().is_err());
+
+        let mut broken = octree.data.clone();
+        broken[leaf].level += 1;
+        let broken = Octree { data: broken, ..Octree::new(octree.depth, octree.size).unwrap() };
+        assert!(broken.validate().is_err());
+
+        let shallower = Octree { data: octree.data.clone(), ..Octree::new(octree.depth - 1, octree.size).unwrap() };
+        assert!(shallower.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_edits_outside_of_the_tree() {
+        assert_eq!(Octree::new(-1, 8.0).err(), Some(EditError::InvalidDepth(-1)));
+        assert_eq!(Octree::new(31, 8.0).err(), Some(EditError::InvalidDepth(31)));
+        let mut octree = Octree::new(3, 8.0).unwrap();
+        for coords in [[-1, 0, 0], [0, 8, 0], [0, 0, i32::MAX], [i32::MIN, 0, 0]] {
+            let [x, y, z] = coords;
+            assert_eq!(octree.set(x, y, z, 3, 1), Err(EditError::OutOfBounds { coords, depth: 3 }));
+            assert_eq!(octree.clear(x, y, z, 3), Err(EditError::OutOfBounds { coords, depth: 3 }));
+        }
+        for depth in [-1, 31, 32, i32::MAX] {
+            assert_eq!(octree.set(0, 0, 0, depth, 1), Err(EditError::InvalidDepth(depth)));
+        }
+        assert_eq!(octree.data.len(), 1);
+        assert_eq!(octree.depth, 3);
+
+        assert_eq!(octree.set(7, 0, 7, 3, 1), Ok(()));
+        assert_eq!(octree.set(1 << 30, 0, 0, 31, 1), Err(EditError::InvalidDepth(31)));
+        assert_eq!(octree.set((1 << 30) - 1, 0, 0, 30, 1), Ok(()));
+        assert_eq!(octree.depth, 30);
+        assert_eq!(octree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn edits_that_change_nothing_keep_the_depth() {
+        let mut octree = Octree::new(2, 8.0).unwrap();
+        octree.clear(0, 0, 0, 5).unwrap();
+        octree.set(0, 0, 0, 1, 1).unwrap();
+        octree.set(3, 3, 3, 4, 1).unwrap();
+        assert_eq!(octree.depth, 2);
+        octree.set(3, 3, 3, 4, 2).unwrap();
+        assert_eq!(octree.depth, 4);
+        assert_eq!(octree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn same_config_gives_the_same_tree() {
+        let config = GeneratorConfig { seed: 9, max_depth: 5, fill_chance: vec![0.0, 0.05, 0.1, 0.15, 0.2, 0.3] };
+        let octree = Octree::new_random(&config, 8.0).unwrap();
+        assert_eq!(octree.data, Octree::new_random(&config, 8.0).unwrap().data);
+        assert_eq!(octree.depth, 5);
+        let other = GeneratorConfig { seed: 10, ..config.clone() };
+        assert_ne!(octree.data, Octree::new_random(&other, 8.0).unwrap().data);
+
+        let mut rng = StdRng::seed_from_u64(9);
+        let with_rng = Octree::new_random_with_rng(config.max_depth, &config.fill_chance, 8.0, &mut rng).unwrap();
+        assert_eq!(octree.data, with_rng.data);
+    }
+
+    #[test]
+    fn rejects_invalid_generator_depths() {
+        for max_depth in [-1, 31] {
+            let config = GeneratorConfig::new(0, max_depth, 0.5);
+            assert_eq!(Octree::new_random(&config, 8.0).err(), Some(EditError::InvalidDepth(max_depth)));
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use crate::octree::{Node, Octree};
+
+// Octree encoding for the GPU where the non empty sub voxels of a node are stored next to each
+// other, so a node only needs two masks and two indices instead of 8 sub voxels and 6 ropes.
+// Leaves are not nodes, their materials live in a separate array. Like the DAG there are no
+// ropes, the shader keeps the path to the current node on a stack.
+pub struct PackedOctree {
+    pub(crate) nodes: Vec<PackedNode>,
+    pub(crate) materials: Vec<i32>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PackedNode {
+    // bit i is set if sub voxel i is not empty, bit 8 + i if it is also a leaf
+    pub(crate) masks: u32,
+    // index in `nodes` of the first sub voxel that is not a leaf, the others follow in order
+    pub(crate) first_child: u32,
+    // index in `materials` of the first leaf sub voxel, the others follow in order
+    pub(crate) first_material: u32,
+}
+
+impl PackedNode {
+    pub const WORDS: usize = 3;
+}
+
+impl PackedOctree {
+    // same as MAX_DEPTH in traverse.glsl, the size of its stack
+    pub const MAX_DEPTH: i32 = 12;
+
+    pub fn from_octree(octree: &Octree) -> Result<Self, String> {
+        if octree.depth > Self::MAX_DEPTH {
+            return Err(format!("depth {} is deeper than the {} levels packed.frag can trace", octree.depth, Self::MAX_DEPTH));
+        }
+        let mut packed = Self { nodes: vec![PackedNode::default()], materials: vec![] };
+        let root = &octree.data[0];
+        if !root.is_leaf() {
+            packed.nodes[0] = packed.pack(&octree.data, 0);
+        } else if root.material_id != Octree::EMPTY {
+            // the root is always a node, a solid root becomes eight solid leaves
+            packed.nodes[0] = PackedNode { masks: 0xffff, first_child: 0, first_material: 0 };
+            packed.materials = vec![root.material_id; 8];
+        }
+        Ok(packed)
+    }
+
+    fn pack(&mut self, data: &[Node], index: i32) -> PackedNode {
+        let node = &data[index as usize];
+        let mut packed = PackedNode { masks: 0, first_child: 0, first_material: self.materials.len() as u32 };
+        let mut children = vec![];
+        for (i, &sub_voxel) in node.sub_voxels.iter().enumerate() {
+            if sub_voxel == 0 {
+                continue;
+            }
+            let child = &data[sub_voxel as usize];
+            if child.is_leaf() {
+                if child.material_id == Octree::EMPTY {
+                    continue;
+                }
+                packed.masks |= 1 << (8 + i);
+                self.materials.push(child.material_id);
+            } else {
+                children.push(sub_voxel);
+            }
+            packed.masks |= 1 << i;
+        }
+        // the slots are reserved first so the sub voxels stay next to each other
+        packed.first_child = self.nodes.len() as u32;
+        self.nodes.resize(self.nodes.len() + children.len(), PackedNode::default());
+        for (offset, child) in children.into_iter().enumerate() {
+            self.nodes[packed.first_child as usize + offset] = self.pack(data, child);
+        }
+        packed
+    }
+
+    // The storage buffer the shaders read: every node as `PackedNode::WORDS` words followed by
+    // the materials, with `first_material` pointing into the same buffer.
+    pub fn to_words(&self) -> Vec<u32> {
+        let base = (self.nodes.len() * PackedNode::WORDS) as u32;
+        let mut words = Vec::with_capacity(self.nodes.len() * PackedNode::WORDS + self.materials.len());
+        for node in &self.nodes {
+            words.extend_from_slice(&[node.masks, node.first_child, base + node.first_material]);
+        }
+        words.extend(self.materials.iter().map(|&material| material as u32));
+        words
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::{PackedNode, PackedOctree};
+    use crate::octree::tests::{assert_voxels, child_index, random_octree};
+    use crate::octree::Octree;
+
+    // Material of the voxel at `coords` in the 2^depth grid, read from the words like
+    // fetch_child in packed.frag
+    fn lookup(words: &[u32], depth: i32, coords: [i32; 3]) -> i32 {
+        let mut node = 0;
+        for shift in (0..depth).rev() {
+            let masks = words[PackedNode::WORDS * node];
+            let bit = 1 << child_index(coords, shift);
+            if masks & bit == 0 {
+                return Octree::EMPTY;
+            }
+            let leaves = masks >> 8;
+            if leaves & bit != 0 {
+                let material = words[PackedNode::WORDS * node + 2] + (leaves & (bit - 1)).count_ones();
+                return words[material as usize] as i32;
+            }
+            node = (words[PackedNode::WORDS * node + 1] + (masks & !leaves & (bit - 1)).count_ones()) as usize;
+        }
+        unreachable!("the deepest nodes only have leaves")
+    }
+
+    #[test]
+    fn matches_the_octree() {
+        let mut rng = StdRng::seed_from_u64(19);
+        for _ in 0..20 {
+            let octree = random_octree(&mut rng, 4);
+            let words = PackedOctree::from_octree(&octree).unwrap().to_words();
+            assert_voxels(&octree, 4, |coords| lookup(&words, 4, coords));
+        }
+    }
+
+    #[test]
+    fn rejects_trees_too_deep_for_the_shader() {
+        for (depth, ok) in [(12, true), (13, false)] {
+            let mut octree = Octree::new(depth, 8.0).unwrap();
+            octree.set(5, 0, 0, depth, 1).unwrap();
+            assert_eq!(PackedOctree::from_octree(&octree).is_ok(), ok, "depth {}", depth);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use cgmath::Vector3;
+
+use crate::octree::Octree;
+
+// A cell of the octree together with the node that covers it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OctreeCell {
+    // index into `data`, None for an empty octant that has no node allocated for it
+    pub node: Option<i32>,
+    pub material_id: i32,
+    pub level: i32,
+    // integer position of the cell among the 2^level cells along each axis
+    pub coords: [i32; 3],
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+// Depth first iterator over the non empty leaves of an octree, from `Octree::leaves` and
+// `Octree::leaves_in`.
+pub struct Leaves<'a> {
+    octree: &'a Octree,
+    // nodes left to visit with their level and coordinates
+    stack: Vec<(i32, i32, [i32; 3])>,
+    region: Option<(Vector3<f32>, Vector3<f32>)>,
+}
+
+impl Octree {
+    pub fn leaves(&self) -> Leaves<'_> {
+        Leaves { octree: self, stack: vec![(0, 0, [0; 3])], region: None }
+    }
+
+    // Only the leaves whose cell touches the box from `min` to `max`, subtrees outside of it are
+    // skipped without visiting them.
+    pub fn leaves_in(&self, min: Vector3<f32>, max: Vector3<f32>) -> Leaves<'_> {
+        Leaves { octree: self, stack: vec![(0, 0, [0; 3])], region: Some((min, max)) }
+    }
+
+    // The leaf containing `pos`, or None if `pos` is outside of the cube of half extent `size`
+    // around the origin that the shaders trace. Points on a face between two cells belong to the
+    // cell on the positive side, points on the positive faces of the cube to the last cell.
+    pub fn get(&self, pos: Vector3<f32>) -> Option<OctreeCell> {
+        self.get_at_level(pos, self.depth)
+    }
+
+    // Like `get` but stops descending at `level`, the returned node can still have sub voxels.
+    // None for levels outside of 0..=MAX_DEPTH as well.
+    pub fn get_at_level(&self, pos: Vector3<f32>, level: i32) -> Option<OctreeCell> {
+        if !(0..=Self::MAX_DEPTH).contains(&level) {
+            return None;
+        }
+        let extent = 1 << level;
+        let cell_size = 2.0 * self.size / extent as f32;
+        let mut coords = [0; 3];
+        for axis in 0..3 {
+            if !(-self.size..=self.size).contains(&pos[axis]) {
+                return None;
+            }
+            coords[axis] = (((pos[axis] + self.size) / cell_size).floor() as i32).clamp(0, extent - 1);
+        }
+
+        let mut node = 0;
+        let mut reached = 0;
+        while reached < level && !self.data[node as usize].is_leaf() {
+            let shift = level - reached - 1;
+            let index = ((coords[0] >> shift) & 1) | ((coords[1] >> shift) & 1) << 1 | ((coords[2] >> shift) & 1) << 2;
+            reached += 1;
+            let sub_voxel = self.data[node as usize].sub_voxels[index as usize];
+            if sub_voxel == 0 {
+                return Some(self.cell(None, Self::EMPTY, reached, coords.map(|coord| coord >> shift)));
+            }
+            node = sub_voxel;
+        }
+        let material_id = self.data[node as usize].material_id;
+        Some(self.cell(Some(node), material_id, reached, coords.map(|coord| coord >> (level - reached))))
+    }
+
+    // World space corners of the cell at `coords` on `level`.
+    pub fn cell_bounds(&self, level: i32, coords: [i32; 3]) -> (Vector3<f32>, Vector3<f32>) {
+        let cell_size = 2.0 * self.size / (1 << level) as f32;
+        let min = coords.map(|coord| -self.size + coord as f32 * cell_size);
+        let max = coords.map(|coord| -self.size + (coord + 1) as f32 * cell_size);
+        (min.into(), max.into())
+    }
+
+    fn cell(&self, node: Option<i32>, material_id: i32, level: i32, coords: [i32; 3]) -> OctreeCell {
+        let (min, max) = self.cell_bounds(level, coords);
+        OctreeCell { node, material_id, level, coords, min, max }
+    }
+}
+
+impl<'a> Iterator for Leaves<'a> {
+    type Item = OctreeCell;
+
+    fn next(&mut self) -> Option<OctreeCell> {
+        while let Some((index, level, coords)) = self.stack.pop() {
+            if let Some((min, max)) = self.region {
+                let (cell_min, cell_max) = self.octree.cell_bounds(level, coords);
+                if (0..3).any(|axis| cell_max[axis] < min[axis] || cell_min[axis] > max[axis]) {
+                    continue;
+                }
+            }
+            let node = &self.octree.data[index as usize];
+            if node.is_leaf() {
+                if node.material_id != Octree::EMPTY {
+                    return Some(self.octree.cell(Some(index), node.material_id, level, coords));
+                }
+                continue;
+            }
+            // pushed in reverse so the sub voxels come out in index order
+            for i in (0..8).rev() {
+                let sub_voxel = node.sub_voxels[i];
+                if sub_voxel != 0 {
+                    let child_coords = [0, 1, 2].map(|axis| coords[axis] * 2 + ((i as i32 >> axis) & 1));
+                    self.stack.push((sub_voxel, level + 1, child_coords));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::OctreeCell;
+    use crate::octree::tests::{random_octree, voxel};
+    use crate::octree::Octree;
+
+    fn random_point(rng: &mut StdRng, range: f32) -> Vector3<f32> {
+        Vector3::new(rng.gen_range(-range..range), rng.gen_range(-range..range), rng.gen_range(-range..range))
+    }
+
+    #[test]
+    fn get_agrees_with_leaves_in() {
+        let mut rng = StdRng::seed_from_u64(21);
+        for _ in 0..10 {
+            let octree = random_octree(&mut rng, 5);
+            for _ in 0..500 {
+                // almost never on a face between two cells
+                let pos = random_point(&mut rng, 10.0);
+                let leaves = octree.leaves_in(pos, pos).collect::<Vec<_>>();
+                match octree.get(pos) {
+                    None => {
+                        assert!((0..3).any(|axis| pos[axis].abs() > 8.0), "{:?} is inside", pos);
+                        assert!(leaves.is_empty());
+                    }
+                    Some(cell) if cell.material_id == Octree::EMPTY => assert!(leaves.is_empty(), "{:?}", pos),
+                    Some(cell) => {
+                        assert_eq!(cell.node.map(|node| octree.data[node as usize].level), Some(cell.level));
+                        assert!((0..3).all(|axis| cell.min[axis] <= pos[axis] && pos[axis] <= cell.max[axis]));
+                        assert_eq!(leaves, vec![cell]);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_in_skips_only_cells_outside_of_the_region() {
+        let mut rng = StdRng::seed_from_u64(22);
+        for _ in 0..10 {
+            let octree = random_octree(&mut rng, 5);
+            let all = octree.leaves().collect::<Vec<_>>();
+            // every solid voxel is covered by exactly one leaf
+            let volume = all.iter().map(|leaf| 1 << (3 * (octree.depth - leaf.level))).sum::<i32>();
+            let expected = (0..32 * 32 * 32)
+                .filter(|i| voxel(&octree, 5, [i % 32, i / 32 % 32, i / 1024]) != Octree::EMPTY)
+                .count();
+            assert_eq!(volume as usize, expected);
+
+            for _ in 0..50 {
+                let (a, b) = (random_point(&mut rng, 10.0), random_point(&mut rng, 10.0));
+                let (min, max) = (a.zip(b, f32::min), a.zip(b, f32::max));
+                let touching = |leaf: &&OctreeCell| {
+                    (0..3).all(|axis| leaf.max[axis] >= min[axis] && leaf.min[axis] <= max[axis])
+                };
+                let expected = all.iter().filter(touching).copied().collect::<Vec<_>>();
+                assert_eq!(octree.leaves_in(min, max).collect::<Vec<_>>(), expected);
+            }
+        }
+    }
+    #[test]
+    fn get_at_level_rejects_invalid_levels() {
+        let octree = Octree::new_wall(3, 8.0);
+        let pos = Vector3::new(1.0, 2.0, 3.0);
+        for level in [-1, 31, i32::MAX, i32::MIN] {
+            assert_eq!(octree.get_at_level(pos, level), None, "level {}", level);
+        }
+        assert!(octree.get_at_level(pos, Octree::MAX_DEPTH).is_some());
+    }
+}
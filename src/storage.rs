@@ -0,0 +1,298 @@
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::octree::{Node, Octree};
+
+// File layout, all values little endian:
+//   magic           [u8; 4]   "SVO\0"
+//   version         u32
+//   depth           i32
+//   size            f32
+//   node count      u32
+//   node size       u32       bytes per node
+//   sub voxel count u32
+//   rope count      u32
+// followed by `node count` nodes, each written as material_id, level, sub_voxels, ropes (i32s).
+const MAGIC: [u8; 4] = *b"SVO\0";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 32;
+const NODE_SIZE: usize = std::mem::size_of::<Node>();
+const SUB_VOXEL_COUNT: usize = 8;
+const ROPE_COUNT: usize = 6;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Octree {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.data.len() * NODE_SIZE);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.depth.to_le_bytes());
+        bytes.extend_from_slice(&self.size.to_le_bytes());
+        bytes.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(NODE_SIZE as u32).to_le_bytes());
+        bytes.extend_from_slice(&(SUB_VOXEL_COUNT as u32).to_le_bytes());
+        bytes.extend_from_slice(&(ROPE_COUNT as u32).to_le_bytes());
+        for node in &self.data {
+            bytes.extend_from_slice(&node.material_id.to_le_bytes());
+            bytes.extend_from_slice(&node.level.to_le_bytes());
+            for sub_voxel in node.sub_voxels {
+                bytes.extend_from_slice(&sub_voxel.to_le_bytes());
+            }
+            for rope in node.ropes {
+                bytes.extend_from_slice(&rope.to_le_bytes());
+            }
+        }
+        fs::write(path, bytes)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        if bytes.len() < HEADER_SIZE {
+            return Err(invalid_data(format!("file is too short for a header: {} bytes", bytes.len())));
+        }
+        let word = |offset: usize| -> [u8; 4] { bytes[offset..offset + 4].try_into().unwrap() };
+        if word(0) != MAGIC {
+            return Err(invalid_data("not an octree file".to_string()));
+        }
+        let version = u32::from_le_bytes(word(4));
+        if version != VERSION {
+            return Err(invalid_data(format!("unsupported version {}", version)));
+        }
+        let depth = i32::from_le_bytes(word(8));
+        let size = f32::from_le_bytes(word(12));
+        let node_count = u32::from_le_bytes(word(16)) as usize;
+        let node_size = u32::from_le_bytes(word(20)) as usize;
+        let sub_voxel_count = u32::from_le_bytes(word(24)) as usize;
+        let rope_count = u32::from_le_bytes(word(28)) as usize;
+        if node_size != NODE_SIZE || sub_voxel_count != SUB_VOXEL_COUNT || rope_count != ROPE_COUNT {
+            return Err(invalid_data(format!(
+                "unsupported node layout: {} bytes, {} sub voxels, {} ropes",
+                node_size, sub_voxel_count, rope_count
+            )));
+        }
+        if !(0..=Self::MAX_DEPTH).contains(&depth) || !size.is_finite() || size <= 0.0 {
+            return Err(invalid_data(format!("invalid depth {} or size {}", depth, size)));
+        }
+        if node_count == 0 || bytes.len() != HEADER_SIZE + node_count * NODE_SIZE {
+            return Err(invalid_data(format!(
+                "expected {} nodes, file has {} bytes of node data",
+                node_count,
+                bytes.len() - HEADER_SIZE
+            )));
+        }
+
+        let data = bytes[HEADER_SIZE..]
+            .chunks_exact(NODE_SIZE)
+            .map(|chunk| {
+                let mut values = chunk.chunks_exact(4).map(|value| i32::from_le_bytes(value.try_into().unwrap()));
+                let mut node = Node {
+                    material_id: values.next().unwrap(),
+                    level: values.next().unwrap(),
+                    ..Default::default()
+                };
+                for sub_voxel in node.sub_voxels.iter_mut() {
+                    *sub_voxel = values.next().unwrap();
+                }
+                for rope in node.ropes.iter_mut() {
+                    *rope = values.next().unwrap();
+                }
+                node
+            })
+            .collect();
+        let mut octree = Self { data, depth, size, free: vec![] };
+        octree.validate_file()?;
+        Ok(octree)
+    }
+
+    // Checks that the nodes reachable from the root form a tree no deeper than `depth` and that
+    // the stored levels and ropes are the ones `generate_ropes` would produce. Nodes that are
+    // not reachable are slots freed by edits and go back on the free list.
+    fn validate_file(&mut self) -> io::Result<()> {
+        let node_count = self.data.len();
+        let mut reachable = vec![false; node_count];
+        reachable[0] = true;
+        let mut stack = vec![(0usize, 0)];
+        while let Some((index, level)) = stack.pop() {
+            if level > self.depth {
+                return Err(invalid_data(format!("node {} is deeper than depth {}", index, self.depth)));
+            }
+            for sub_voxel in self.data[index].sub_voxels {
+                if sub_voxel == 0 {
+                    continue;
+                }
+                if sub_voxel < 0 || sub_voxel as usize >= node_count {
+                    return Err(invalid_data(format!("node {} has sub voxel {} out of bounds", index, sub_voxel)));
+                }
+                if reachable[sub_voxel as usize] {
+                    return Err(invalid_data(format!("node {} is referenced more than once", sub_voxel)));
+                }
+                reachable[sub_voxel as usize] = true;
+                stack.push((sub_voxel as usize, level + 1));
+            }
+        }
+
+        let mut expected = self.data.clone();
+        Self::generate_ropes(&mut expected);
+        for (index, node) in self.data.iter().enumerate() {
+            if !reachable[index] {
+                continue;
+            }
+            if node.level != expected[index].level {
+                return Err(invalid_data(format!(
+                    "node {} has level {}, expected {}",
+                    index, node.level, expected[index].level
+                )));
+            }
+            if node.ropes != expected[index].ropes {
+                return Err(invalid_data(format!(
+                    "node {} has ropes {:?}, expected {:?}",
+                    index, node.ropes, expected[index].ropes
+                )));
+            }
+        }
+
+        self.free = (0..node_count as i32).filter(|&index| !reachable[index as usize]).collect();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io;
+    use std::path::PathBuf;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::{HEADER_SIZE, NODE_SIZE};
+    use crate::octree::{GeneratorConfig, Octree};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("storage-{}-{}.svo", std::process::id(), name))
+    }
+
+    // Saves `octree`, lets `corrupt` change the bytes and loads them again
+    fn reload(octree: &Octree, name: &str, corrupt: impl FnOnce(&mut Vec<u8>)) -> io::Result<Octree> {
+        let path = temp_path(name);
+        octree.save(&path)?;
+        let mut bytes = fs::read(&path)?;
+        corrupt(&mut bytes);
+        fs::write(&path, bytes)?;
+        let loaded = Octree::load(&path);
+        fs::remove_file(&path)?;
+        loaded
+    }
+
+    // Overwrites the i32 at byte `offset` of node `index`: 0 is the material, 4 the level, 8 the
+    // first sub voxel and 40 the first rope.
+    fn write_node(bytes: &mut [u8], index: i32, offset: usize, value: i32) {
+        let start = HEADER_SIZE + index as usize * NODE_SIZE + offset;
+        bytes[start..start + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    // A sub voxel of `index` that has sub voxels itself, and its position in `index`.
+    fn inner_sub_voxel(octree: &Octree, index: i32) -> (usize, i32) {
+        let sub_voxels = octree.data[index as usize].sub_voxels;
+        (0..8)
+            .map(|i| (i, sub_voxels[i]))
+            .find(|&(_, sub_voxel)| sub_voxel != 0 && !octree.data[sub_voxel as usize].is_leaf())
+            .unwrap()
+    }
+
+    fn edited_octree() -> Octree {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut octree = Octree::new_random(&GeneratorConfig::new(5, 4, 0.3), 8.0).unwrap();
+        octree.optimize();
+        // leaves released slots behind
+        for _ in 0..100 {
+            let [x, y, z] = [0; 3].map(|_| rng.gen_range(0..16));
+            octree.set(x, y, z, 4, rng.gen_range(0..3)).unwrap();
+        }
+        octree
+    }
+
+    #[test]
+    fn round_trip() {
+        let octree = edited_octree();
+        let loaded = reload(&octree, "round-trip", |_| {}).unwrap();
+        assert_eq!((loaded.depth, loaded.size), (octree.depth, octree.size));
+        assert_eq!(bytemuck::cast_slice::<_, u8>(&loaded.data), bytemuck::cast_slice::<_, u8>(&octree.data));
+        let mut free = octree.free.clone();
+        free.sort_unstable();
+        assert_eq!(loaded.free, free);
+        assert_eq!(loaded.validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let octree = edited_octree();
+        for len in [0, 3, HEADER_SIZE - 1, HEADER_SIZE, HEADER_SIZE + 10] {
+            let err = reload(&octree, "truncated", |bytes| bytes.truncate(len)).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{} bytes", len);
+        }
+        let err = reload(&octree, "truncated", |bytes| bytes.truncate(bytes.len() - 4)).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let err = reload(&edited_octree(), "magic", |bytes| bytes[..4].copy_from_slice(b"OBJ\0")).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_sub_voxels() {
+        let octree = edited_octree();
+        let node_count = octree.data.len() as i32;
+        for sub_voxel in [node_count, -1, i32::MAX] {
+            // the first sub voxel of the root
+            let err = reload(&octree, "bounds", |bytes| {
+                bytes[HEADER_SIZE + 8..HEADER_SIZE + 12].copy_from_slice(&sub_voxel.to_le_bytes())
+            })
+            .err()
+            .unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "sub voxel {}", sub_voxel);
+        }
+    }
+    #[test]
+    fn rejects_shared_sub_voxels() {
+        let octree = edited_octree();
+        let (i, child) = inner_sub_voxel(&octree, 0);
+        let other = (i + 1) % 8;
+        let err = reload(&octree, "shared", |bytes| write_node(bytes, 0, 8 + other * 4, child)).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_cycles() {
+        let octree = edited_octree();
+        let (_, child) = inner_sub_voxel(&octree, 0);
+        let (i, grandchild) = inner_sub_voxel(&octree, child);
+        // a grandchild pointing back to its parent
+        let err = reload(&octree, "cycle", |bytes| write_node(bytes, grandchild, 8, child)).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // a node pointing to itself
+        let err = reload(&octree, "cycle", |bytes| write_node(bytes, child, 8 + i * 4, child)).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_wrong_levels_and_ropes() {
+        let octree = edited_octree();
+        let (_, child) = inner_sub_voxel(&octree, 0);
+        let node = octree.data[child as usize];
+        let err = reload(&octree, "level", |bytes| write_node(bytes, child, 4, node.level + 1)).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        for dir in 0..6 {
+            let rope = if node.ropes[dir] == 0 { child } else { 0 };
+            let err = reload(&octree, "rope", |bytes| write_node(bytes, child, 40 + dir * 4, rope)).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "rope {}", dir);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use crate::noise::Noise;
+use crate::octree::{Node, Octree};
+
+// Terrain generators. Both build the tree top-down and only subdivide cells that can contain
+// the surface, so solid ground and open sky end up as single large leaves.
+impl Octree {
+    // Columns of solid voxels along z, as high as `noise` says at the column center.
+    pub fn new_heightfield(depth: i32, size: f32, noise: &Noise) -> Result<Self, String> {
+        Self::check_depth(depth).map_err(|err| err.to_string())?;
+        let n = 1usize << depth;
+        // one height per column of the finest level, the coarser levels take a third of that
+        let mut heights = Vec::new();
+        n.checked_mul(n)
+            .and_then(|columns| heights.try_reserve_exact(columns).ok())
+            .ok_or_else(|| format!("a heightfield at depth {} does not fit in memory", depth))?;
+        heights.extend((0..n * n).map(|i| {
+            let (u, v) = (((i % n) as f32 + 0.5) / n as f32, ((i / n) as f32 + 0.5) / n as f32);
+            let height = ((noise.get2(u, v) * 0.5 + 0.5) * n as f32).round().clamp(0.0, n as f32) as i32;
+            (height, height)
+        }));
+        // min and max column height of every cell footprint, per level
+        let mut pyramid = vec![heights];
+        for level in (0..depth).rev() {
+            let n = 1usize << level;
+            let finer = pyramid.last().unwrap();
+            let coarser = (0..n * n)
+                .map(|i| {
+                    let (x, y) = (i % n * 2, i / n * 2);
+                    [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)]
+                        .iter()
+                        .map(|&(x, y)| finer[x + y * n * 2])
+                        .fold((i32::MAX, i32::MIN), |(min, max), (lo, hi)| (min.min(lo), max.max(hi)))
+                })
+                .collect();
+            pyramid.push(coarser);
+        }
+        pyramid.reverse();
+
+        let classify = |level: i32, coords: [i32; 3]| {
+            let n = 1 << level;
+            let extent = 1 << (depth - level);
+            let (min, max) = pyramid[level as usize][(coords[0] + coords[1] * n) as usize];
+            if coords[2] * extent >= max {
+                Some(Self::EMPTY)
+            } else if (coords[2] + 1) * extent <= min {
+                Some(Self::SOLID)
+            } else {
+                None
+            }
+        };
+        Ok(Self::new_terrain(depth, size, &classify))
+    }
+
+    // Solid wherever `noise` plus a falloff with height is positive, which gives overhangs
+    // and caves a heightfield cannot express.
+    pub fn new_density(depth: i32, size: f32, noise: &Noise) -> Result<Self, String> {
+        Self::check_depth(depth).map_err(|err| err.to_string())?;
+        let density = |u: f32, v: f32, w: f32| noise.get3(u, v, w) + 1.0 - 2.0 * w;
+        let lipschitz = noise.lipschitz() + 2.0;
+
+        let classify = |level: i32, coords: [i32; 3]| {
+            let extent = 1.0 / (1 << level) as f32;
+            let center = coords.map(|coord| (coord as f32 + 0.5) * extent);
+            let value = density(center[0], center[1], center[2]);
+            // how far the density can stray from the center value inside the cell
+            let bound = if level == depth { 0.0 } else { lipschitz * extent * 0.5 * 3f32.sqrt() };
+            if value - bound > 0.0 {
+                Some(Self::SOLID)
+            } else if value + bound <= 0.0 {
+                Some(Self::EMPTY)
+            } else {
+                None
+            }
+        };
+        Ok(Self::new_terrain(depth, size, &classify))
+    }
+
+    // `classify` returns the material of cells that are uniform and None for cells that have
+    // to be subdivided. Cells at `depth` must always be uniform.
+    pub(crate) fn new_terrain(depth: i32, size: f32, classify: &dyn Fn(i32, [i32; 3]) -> Option<i32>) -> Self {
+        let mut data = vec![];
+        match classify(0, [0; 3]) {
+            Some(material_id) => data.push(Node { material_id, ..Default::default() }),
+            None => {
+                Self::new_terrain_internal(0, [0; 3], classify, &mut data);
+            }
+        }
+        let mut octree = Self { data, depth, size, free: vec![] };
+        octree.optimize();
+        octree
+    }
+
+    fn new_terrain_internal(
+        level: i32,
+        coords: [i32; 3],
+        classify: &dyn Fn(i32, [i32; 3]) -> Option<i32>,
+        data: &mut Vec<Node>,
+    ) -> i32 {
+        let index = data.len();
+        data.push(Node { level, ..Default::default() });
+        for i in 0..8 {
+            let child_coords = [coords[0] * 2 + (i & 1), coords[1] * 2 + ((i >> 1) & 1), coords[2] * 2 + ((i >> 2) & 1)];
+            let sub_voxel = match classify(level + 1, child_coords) {
+                Some(Self::EMPTY) => 0,
+                Some(material_id) => {
+                    data.push(Node { material_id, level: level + 1, ..Default::default() });
+                    data.len() as i32 - 1
+                }
+                None => Self::new_terrain_internal(level + 1, child_coords, classify, data),
+            };
+            data[index].sub_voxels[i as usize] = sub_voxel;
+        }
+        index as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::noise::Noise;
+    use crate::octree::tests::assert_voxels;
+    use crate::octree::Octree;
+
+    #[test]
+    fn same_seed_gives_the_same_terrain() {
+        let noise = Noise::new(7);
+        let first = Octree::new_heightfield(5, 8.0, &noise).unwrap();
+        let second = Octree::new_heightfield(5, 8.0, &Noise::new(7)).unwrap();
+        assert_eq!(first.data, second.data);
+        let other = Octree::new_heightfield(5, 8.0, &Noise::new(8)).unwrap();
+        assert_ne!(first.data, other.data);
+
+        let first = Octree::new_density(5, 8.0, &noise).unwrap();
+        let second = Octree::new_density(5, 8.0, &Noise::new(7)).unwrap();
+        assert_eq!(first.data, second.data);
+        let other = Octree::new_density(5, 8.0, &Noise::new(8)).unwrap();
+        assert_ne!(first.data, other.data);
+    }
+
+    #[test]
+    fn heightfield_matches_the_noise_per_column() {
+        for seed in 0..4 {
+            let noise = Noise::new(seed);
+            let octree = Octree::new_heightfield(5, 8.0, &noise).unwrap();
+            assert_eq!(octree.validate(), Ok(()));
+            assert_voxels(&octree, 5, |[x, y, z]| {
+                let (u, v) = ((x as f32 + 0.5) / 32.0, (y as f32 + 0.5) / 32.0);
+                let height = ((noise.get2(u, v) * 0.5 + 0.5) * 32.0).round() as i32;
+                if z < height {
+                    Octree::SOLID
+                } else {
+                    Octree::EMPTY
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn density_matches_the_noise_per_voxel() {
+        for seed in 0..4 {
+            let noise = Noise::new(seed);
+            let octree = Octree::new_density(5, 8.0, &noise).unwrap();
+            assert_eq!(octree.validate(), Ok(()));
+            assert_voxels(&octree, 5, |coords| {
+                let [u, v, w] = coords.map(|coord| (coord as f32 + 0.5) / 32.0);
+                if noise.get3(u, v, w) + 1.0 - 2.0 * w > 0.0 {
+                    Octree::SOLID
+                } else {
+                    Octree::EMPTY
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_depths() {
+        let noise = Noise::new(0);
+        assert!(Octree::new_heightfield(-1, 8.0, &noise).is_err());
+        assert!(Octree::new_heightfield(31, 8.0, &noise).is_err());
+        assert!(Octree::new_density(-1, 8.0, &noise).is_err());
+        assert!(Octree::new_density(31, 8.0, &noise).is_err());
+    }
+}
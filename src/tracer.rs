@@ -0,0 +1,405 @@
+use cgmath::{InnerSpace, Vector3};
+
+use crate::octree::Octree;
+use crate::uniforms::Uniforms;
+
+// same bound as the loop in shader.frag
+const MAX_STEPS: usize = 100000;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    pub dir: Vector3<f32>,
+    pub inv_dir: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: Vector3<f32>, dir: Vector3<f32>) -> Self {
+        let dir = dir.normalize();
+        Self {
+            origin,
+            dir,
+            inv_dir: Vector3::new(1.0 / dir.x, 1.0 / dir.y, 1.0 / dir.z),
+        }
+    }
+
+    pub fn at(&self, t: f32) -> Vector3<f32> {
+        self.origin + self.dir * t
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Hit {
+    pub position: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    pub distance: f32,
+    pub node: i32,
+    pub material_id: i32,
+}
+
+// A cell the ray is currently in. Either the node itself, or (if `octant` is set) an empty
+// octant of `node` that has no sub voxel allocated for it.
+#[derive(Clone, Copy, Debug)]
+struct Cell {
+    node: i32,
+    level: i32,
+    coords: [i32; 3],
+    octant: bool,
+}
+
+// Integer coordinate of the face the ray just crossed, used to pick sub voxels along the
+// crossing axis without relying on the floating point hit position.
+#[derive(Clone, Copy, Debug)]
+struct Crossing {
+    axis: usize,
+    level: i32,
+    coord: i32,
+}
+
+pub fn generate_ray(uniforms: &Uniforms, frag_x: f32, frag_y: f32) -> Ray {
+    let view_pos = Vector3::from(uniforms.view_pos);
+    let view_dir = Vector3::from(uniforms.view_dir);
+    let view_up = Vector3::from(uniforms.view_up);
+    let view_right = Vector3::from(uniforms.view_right);
+
+    let x_ratio = frag_x / uniforms.width as f32;
+    let y_ratio = frag_y / uniforms.height as f32;
+    let aspect = uniforms.width as f32 / uniforms.height as f32;
+    let a = (uniforms.fov / 2.0).tan();
+    let a2 = a / aspect;
+    let view_center = view_pos + view_dir;
+
+    let target = view_center
+        + view_right * a * (2.0 * x_ratio - 1.0)
+        + view_up * a2 * (2.0 * y_ratio - 1.0);
+    Ray::new(view_pos, target - view_pos)
+}
+
+// Slab test against the cube of half extent `size` around `origin`. Returns the entry and exit
+// distance together with the axis of the entry face.
+fn intersect(ray: &Ray, origin: Vector3<f32>, size: f32) -> Option<(f32, f32, usize)> {
+    let mut ts_min = f32::NEG_INFINITY;
+    let mut ts_max = f32::INFINITY;
+    let mut entry_axis = 0;
+    for axis in 0..3 {
+        let point = ray.origin[axis] - origin[axis];
+        if ray.dir[axis] == 0.0 {
+            if point < -size || point > size {
+                return None;
+            }
+            continue;
+        }
+        let t_minus = (-size - point) * ray.inv_dir[axis];
+        let t_plus = (size - point) * ray.inv_dir[axis];
+        let t_min = t_minus.min(t_plus);
+        if t_min > ts_min {
+            ts_min = t_min;
+            entry_axis = axis;
+        }
+        ts_max = ts_max.min(t_minus.max(t_plus));
+    }
+    if ts_min <= ts_max && ts_max >= 0.0 {
+        Some((ts_min, ts_max, entry_axis))
+    } else {
+        None
+    }
+}
+
+fn cell_size(octree: &Octree, level: i32) -> f32 {
+    2.0 * octree.size / (1 << level) as f32
+}
+
+// Index of the sub voxel of `cell` that contains `point`. Points exactly on a splitting plane
+// belong to the half the ray is heading into, like in getSubvoxel.
+fn get_subvoxel(
+    octree: &Octree,
+    cell: &Cell,
+    point: Vector3<f32>,
+    ray: &Ray,
+    crossing: Option<Crossing>,
+) -> (usize, [i32; 3]) {
+    let half = cell_size(octree, cell.level + 1);
+    let mut subvoxel = 0;
+    let mut coords = [0; 3];
+    for axis in 0..3 {
+        let bit = match crossing {
+            Some(crossing) if crossing.axis == axis => {
+                if cell.level < crossing.level {
+                    (crossing.coord >> (crossing.level - cell.level - 1)) & 1
+                } else if ray.dir[axis] > 0.0 {
+                    0
+                } else {
+                    1
+                }
+            }
+            _ => {
+                let center = -octree.size + (2 * cell.coords[axis] + 1) as f32 * half;
+                (point[axis] > center || (point[axis] == center && ray.dir[axis] >= 0.0)) as i32
+            }
+        };
+        subvoxel |= (bit as usize) << axis;
+        coords[axis] = cell.coords[axis] * 2 + bit;
+    }
+    (subvoxel, coords)
+}
+
+fn descend(
+    octree: &Octree,
+    mut cell: Cell,
+    point: Vector3<f32>,
+    ray: &Ray,
+    crossing: Option<Crossing>,
+) -> Cell {
+    loop {
+        let node = &octree.data[cell.node as usize];
+        if node.is_leaf() {
+            return cell;
+        }
+        let (subvoxel, coords) = get_subvoxel(octree, &cell, point, ray, crossing);
+        let child = node.sub_voxels[subvoxel];
+        let level = cell.level + 1;
+        if child == 0 {
+            return Cell { node: cell.node, level, coords, octant: true };
+        }
+        cell = Cell { node: child, level, coords, octant: false };
+    }
+}
+
+pub fn trace(octree: &Octree, ray: &Ray) -> Option<Hit> {
+    let (t_min, _, entry_axis) = intersect(ray, Vector3::new(0.0, 0.0, 0.0), octree.size)?;
+    let mut t = t_min.max(0.0);
+    let mut point = ray.at(t);
+    let mut normal = Vector3::new(0.0, 0.0, 0.0);
+    normal[entry_axis] = -ray.dir[entry_axis].signum();
+    let mut crossing = None;
+    if t_min > 0.0 {
+        point[entry_axis] = normal[entry_axis] * octree.size;
+        crossing = Some(Crossing { axis: entry_axis, level: 0, coord: 0 });
+    }
+    let mut start = Cell { node: 0, level: 0, coords: [0; 3], octant: false };
+
+    for _ in 0..MAX_STEPS {
+        let cell = descend(octree, start, point, ray, crossing);
+        let node = &octree.data[cell.node as usize];
+        if !cell.octant && node.material_id != Octree::EMPTY {
+            return Some(Hit {
+                position: point,
+                normal,
+                distance: t,
+                node: cell.node,
+                material_id: node.material_id,
+            });
+        }
+
+        let size = cell_size(octree, cell.level);
+        let mut t_exit = f32::INFINITY;
+        let mut exit_axis = 0;
+        let mut exit_bound = 0.0;
+        for axis in 0..3 {
+            if ray.dir[axis] == 0.0 {
+                continue;
+            }
+            let positive = ray.dir[axis] > 0.0;
+            let bound = -octree.size + (cell.coords[axis] + positive as i32) as f32 * size;
+            let t_axis = (bound - ray.origin[axis]) * ray.inv_dir[axis];
+            if t_axis < t_exit {
+                t_exit = t_axis;
+                exit_axis = axis;
+                exit_bound = bound;
+            }
+        }
+
+        let step = if ray.dir[exit_axis] > 0.0 { 1 } else { -1 };
+        let mut neighbor = cell.coords;
+        neighbor[exit_axis] += step;
+
+        t = t_exit.max(t);
+        point = ray.at(t);
+        point[exit_axis] = exit_bound;
+        normal = Vector3::new(0.0, 0.0, 0.0);
+        normal[exit_axis] = -step as f32;
+        crossing = Some(Crossing { axis: exit_axis, level: cell.level, coord: neighbor[exit_axis] });
+
+        if cell.octant && neighbor[exit_axis] >> 1 == cell.coords[exit_axis] >> 1 {
+            // still inside the parent, move over to the sibling octant
+            start = Cell {
+                node: cell.node,
+                level: cell.level - 1,
+                coords: [cell.coords[0] >> 1, cell.coords[1] >> 1, cell.coords[2] >> 1],
+                octant: false,
+            };
+            continue;
+        }
+
+        let face = exit_axis * 2 + (step > 0) as usize;
+        let rope = node.ropes[face];
+        if rope == -1 {
+            return None;
+        }
+        let level = octree.data[rope as usize].level;
+        let shift = cell.level - level;
+        start = Cell {
+            node: rope,
+            level,
+            coords: [neighbor[0] >> shift, neighbor[1] >> shift, neighbor[2] >> shift],
+            octant: false,
+        };
+    }
+    None
+}
+
+pub fn render(octree: &Octree, uniforms: &Uniforms) -> Vec<u8> {
+    let width = uniforms.width.max(0) as usize;
+    let height = uniforms.height.max(0) as usize;
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let ray = generate_ray(uniforms, x as f32 + 0.5, y as f32 + 0.5);
+            let color = match trace(octree, &ray) {
+                Some(hit) => {
+                    let color = hit.normal * 0.5 + Vector3::new(0.5, 0.5, 0.5);
+                    [
+                        (color.x * 255.0) as u8,
+                        (color.y * 255.0) as u8,
+                        (color.z * 255.0) as u8,
+                        255,
+                    ]
+                }
+                None => [0, 0, 0, 255],
+            };
+            pixels.extend_from_slice(&color);
+        }
+    }
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Vector3};
+
+    use super::{generate_ray, render, trace, Ray};
+    use crate::octree::{GeneratorConfig, Octree};
+    use crate::uniforms::Uniforms;
+
+    // FNV-1a of `render` for the scene below, update it when the tracer changes on purpose
+    const GOLDEN_RENDER: u64 = 1542877196808893014;
+
+    fn hash(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+    }
+
+    // A single voxel of material 5 spanning -4..0 on every axis
+    fn single_voxel() -> Octree {
+        let mut octree = Octree::new(2, 8.0).unwrap();
+        octree.set(1, 1, 1, 2, 5).unwrap();
+        octree
+    }
+
+    // The first solid cell along the ray found by small steps, independent of the ropes
+    fn march(octree: &Octree, ray: &Ray) -> Option<f32> {
+        (0..8000).map(|step| step as f32 * 0.01).find(|&t| {
+            matches!(octree.get(ray.at(t)), Some(cell) if cell.material_id != Octree::EMPTY)
+        })
+    }
+
+    // Whether `point` is next to an edge of a solid cell, where a ray can graze the cell by less
+    // than a marching step
+    fn near_edge(octree: &Octree, point: Vector3<f32>) -> bool {
+        let offsets = (0..27).map(|i| Vector3::new((i % 3 - 1) as f32, (i / 3 % 3 - 1) as f32, (i / 9 - 1) as f32));
+        offsets.filter_map(|offset| octree.get(point + offset * 0.02)).any(|cell| {
+            let near = (0..3).filter(|&axis| (point[axis] - cell.min[axis]).abs().min((point[axis] - cell.max[axis]).abs()) < 0.02);
+            cell.material_id != Octree::EMPTY && near.count() >= 2
+        })
+    }
+
+    fn assert_hit(octree: &Octree, ray: Ray, distance: f32, normal: Vector3<f32>) {
+        let hit = trace(octree, &ray).unwrap_or_else(|| panic!("{:?} missed", ray));
+        assert_eq!(hit.material_id, 5, "{:?}", ray);
+        assert!((hit.distance - distance).abs() < 1e-4, "{:?} hit at {}", ray, hit.distance);
+        assert_eq!(hit.normal, normal, "{:?}", ray);
+        assert!((hit.position - ray.at(distance)).magnitude() < 1e-4, "{:?}", ray);
+    }
+
+    #[test]
+    fn renders_golden_image() {
+        let mut octree = Octree::new_random(&GeneratorConfig::new(3, 4, 0.05), 8.0).unwrap();
+        octree.optimize();
+        let view_pos = Vector3::new(-14.0, 10.0, -18.0);
+        let view_dir = (-view_pos).normalize();
+        let view_right = Vector3::unit_y().cross(view_dir).normalize();
+        let view_up = view_dir.cross(view_right);
+        let uniforms = Uniforms {
+            view_pos: view_pos.into(),
+            view_dir: view_dir.into(),
+            view_up: view_up.into(),
+            view_right: view_right.into(),
+            fov: 1.0,
+            width: 32,
+            height: 24,
+            octree_size: octree.size,
+            octree_depth: octree.depth,
+        };
+        let pixels = render(&octree, &uniforms);
+        assert_eq!(pixels.len(), 32 * 24 * 4);
+        assert!(pixels.chunks(4).any(|pixel| pixel[..3] != [0, 0, 0]), "nothing was hit");
+        assert!(pixels.chunks(4).any(|pixel| pixel[..3] == [0, 0, 0]), "everything was hit");
+        for (i, pixel) in pixels.chunks(4).enumerate() {
+            let ray = generate_ray(&uniforms, (i % 32) as f32 + 0.5, (i / 32) as f32 + 0.5);
+            let marched = march(&octree, &ray);
+            match (trace(&octree, &ray), marched) {
+                (Some(hit), Some(t)) if (hit.distance - t).abs() < 0.02 => {}
+                (Some(hit), _) => assert!(near_edge(&octree, hit.position), "pixel {} hit at {:?}", i, hit),
+                (None, Some(t)) => assert!(near_edge(&octree, ray.at(t)), "pixel {} missed at {}", i, t),
+                (None, None) => {}
+            }
+            assert_eq!(pixel[..3] != [0, 0, 0], trace(&octree, &ray).is_some());
+        }
+        assert_eq!(hash(&pixels), GOLDEN_RENDER);
+    }
+
+    #[test]
+    fn hits_along_every_axis() {
+        let octree = single_voxel();
+        let center = Vector3::new(-2.0, -2.0, -2.0);
+        for axis in 0..3 {
+            for sign in [-1.0, 1.0] {
+                let mut dir = Vector3::new(0.0, 0.0, 0.0);
+                dir[axis] = sign;
+                assert_hit(&octree, Ray::new(center - dir * 20.0, dir), 18.0, -dir);
+            }
+        }
+    }
+
+    #[test]
+    fn hits_from_inside_the_tree() {
+        let octree = single_voxel();
+        // from an empty cell
+        assert_hit(&octree, Ray::new(Vector3::new(6.0, -1.0, -3.0), -Vector3::unit_x()), 6.0, Vector3::unit_x());
+        assert_hit(&octree, Ray::new(Vector3::new(-1.0, 6.0, -3.0), -Vector3::unit_y()), 6.0, Vector3::unit_y());
+        // from inside the voxel itself
+        let hit = trace(&octree, &Ray::new(Vector3::new(-2.0, -1.0, -3.0), Vector3::new(1.0, 2.0, 3.0))).unwrap();
+        assert_eq!(hit.material_id, 5);
+        assert_eq!(hit.distance, 0.0);
+    }
+
+    #[test]
+    fn misses() {
+        let octree = single_voxel();
+        let rays = [
+            // pointing away from the cube
+            Ray::new(Vector3::new(-20.0, -2.0, -2.0), -Vector3::unit_x()),
+            // passing beside the cube
+            Ray::new(Vector3::new(-20.0, 20.0, -2.0), Vector3::unit_x()),
+            // through the cube but only empty cells
+            Ray::new(Vector3::new(-20.0, 6.0, 6.0), Vector3::unit_x()),
+            Ray::new(Vector3::new(-20.0, -20.0, 2.0), Vector3::new(1.0, 1.0, 0.0)),
+            // from inside, away from the voxel
+            Ray::new(Vector3::new(6.0, 6.0, 6.0), Vector3::unit_y()),
+            Ray::new(Vector3::new(-2.0, 2.0, -2.0), Vector3::new(0.3, 1.0, -0.2)),
+        ];
+        for ray in rays {
+            assert!(trace(&octree, &ray).is_none(), "{:?} hit", ray);
+        }
+        assert!(trace(&Octree::new(0, 8.0).unwrap(), &Ray::new(Vector3::new(0.0, 0.0, -20.0), Vector3::unit_z())).is_none());
+    }
+}
@@ -0,0 +1,77 @@
+use crevice::std140::AsStd140;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, AsStd140)]
+pub struct Uniforms {
+    pub view_pos: mint::Vector3<f32>,
+    pub view_dir: mint::Vector3<f32>,
+    pub view_up: mint::Vector3<f32>,
+    pub view_right: mint::Vector3<f32>,
+    pub fov: f32,
+    pub width: i32,
+    pub height: i32,
+    pub octree_size: f32,
+    pub octree_depth: i32,
+}
+
+impl Uniforms {
+    pub fn to_json(&self) -> String {
+        let vector = |v: mint::Vector3<f32>| format!("[{}, {}, {}]", v.x, v.y, v.z);
+        let fields = [
+            ("view_pos", vector(self.view_pos)),
+            ("view_dir", vector(self.view_dir)),
+            ("view_up", vector(self.view_up)),
+            ("view_right", vector(self.view_right)),
+            ("fov", self.fov.to_string()),
+            ("width", self.width.to_string()),
+            ("height", self.height.to_string()),
+            ("octree_size", self.octree_size.to_string()),
+            ("octree_depth", self.octree_depth.to_string()),
+        ];
+        let fields = fields.iter().map(|(name, value)| format!("  \"{}\": {}", name, value)).collect::<Vec<_>>();
+        format!("{{\n{}\n}}\n", fields.join(",\n"))
+    }
+}
+
+// Values that change every frame, bound next to `Uniforms`. New fields can be appended here
+// and in the shaders without touching the bind groups.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, AsStd140)]
+pub struct FrameConstants {
+    pub time: f32,
+    pub frame_index: u32,
+    // sub pixel offset in [-0.5, 0.5), a Halton (2, 3) sequence over the frame index
+    pub jitter: mint::Vector2<f32>,
+}
+
+impl FrameConstants {
+    pub fn new(time: f32, frame_index: u32) -> Self {
+        let halton = |mut index: u32, base: u32| {
+            let mut result = 0.0;
+            let mut fraction = 1.0 / base as f32;
+            while index > 0 {
+                result += (index % base) as f32 * fraction;
+                index /= base;
+                fraction /= base as f32;
+            }
+            // long indices round up to 1.0 in f32
+            result.min(1.0 - f32::EPSILON / 2.0)
+        };
+        let index = frame_index.wrapping_add(1);
+        let jitter = [halton(index, 2) - 0.5, halton(index, 3) - 0.5];
+        Self { time, frame_index, jitter: jitter.into() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jitter_stays_in_range() {
+        for frame_index in (0..64).chain([u32::MAX - 1, u32::MAX]) {
+            let jitter = FrameConstants::new(0.0, frame_index).jitter;
+            assert!((-0.5..0.5).contains(&jitter.x) && (-0.5..0.5).contains(&jitter.y));
+        }
+    }
+}
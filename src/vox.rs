@@ -0,0 +1,474 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::octree::Octree;
+
+// Importer for MagicaVoxel .vox files, see
+// https://github.com/ephtracy/voxel-model/blob/master/MagicaVoxel-file-format-vox.txt
+//
+// Material ids are the palette indices of the file (1 to 255), the returned palette maps them
+// to RGBA colors. Index 0 is empty.
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() - self.offset < len {
+            return Err(invalid_data(format!("unexpected end of file at byte {}", self.offset)));
+        }
+        let bytes = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn len(&mut self) -> io::Result<usize> {
+        let len = self.i32()?;
+        if len < 0 {
+            return Err(invalid_data(format!("negative length {} at byte {}", len, self.offset - 4)));
+        }
+        Ok(len as usize)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.len()?;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn dict(&mut self) -> io::Result<HashMap<String, String>> {
+        let len = self.len()?;
+        (0..len).map(|_| Ok((self.string()?, self.string()?))).collect()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.offset == self.bytes.len()
+    }
+}
+
+struct Model {
+    size: [i32; 3],
+    voxels: Vec<([i32; 3], u8)>,
+}
+
+enum SceneNode {
+    Transform { child: i32, translation: [i32; 3], rotation: [[i32; 3]; 3] },
+    Group { children: Vec<i32> },
+    Shape { models: Vec<i32> },
+}
+
+const IDENTITY: [[i32; 3]; 3] = [[1, 0, 0], [0, 1, 0], [0, 0, 1]];
+
+pub fn load_vox<P: AsRef<Path>>(path: P, size: f32) -> io::Result<(Octree, Vec<[u8; 4]>)> {
+    parse_vox(&fs::read(path)?, size)
+}
+
+fn parse_vox(bytes: &[u8], size: f32) -> io::Result<(Octree, Vec<[u8; 4]>)> {
+    let mut reader = Reader { bytes, offset: 0 };
+    if reader.take(4)? != b"VOX " {
+        return Err(invalid_data("not a MagicaVoxel file".to_string()));
+    }
+    reader.i32()?;
+
+    let mut models = vec![];
+    let mut size_of_next_model = None;
+    let mut palette = None;
+    let mut scene = HashMap::new();
+    while !reader.is_empty() {
+        let id = reader.take(4)?;
+        let content_len = reader.len()?;
+        reader.len()?;
+        // MAIN has no content of its own, the remaining chunks are its children
+        if id == b"MAIN" {
+            continue;
+        }
+        let mut chunk = Reader { bytes: reader.take(content_len)?, offset: 0 };
+        match id {
+            b"SIZE" => size_of_next_model = Some([chunk.i32()?, chunk.i32()?, chunk.i32()?]),
+            b"XYZI" => {
+                let size = size_of_next_model
+                    .take()
+                    .ok_or_else(|| invalid_data("XYZI chunk without SIZE chunk".to_string()))?;
+                let count = chunk.len()?;
+                let voxels = chunk
+                    .take(count * 4)?
+                    .chunks_exact(4)
+                    .map(|voxel| ([voxel[0] as i32, voxel[1] as i32, voxel[2] as i32], voxel[3]))
+                    .collect();
+                models.push(Model { size, voxels });
+            }
+            b"RGBA" => {
+                let colors = chunk.take(256 * 4)?;
+                // color i of the chunk belongs to palette index i + 1
+                let mut rgba = vec![[0; 4]];
+                rgba.extend(colors.chunks_exact(4).take(255).map(|color| [color[0], color[1], color[2], color[3]]));
+                palette = Some(rgba);
+            }
+            b"nTRN" => {
+                let id = chunk.i32()?;
+                chunk.dict()?;
+                let child = chunk.i32()?;
+                chunk.i32()?;
+                chunk.i32()?;
+                let frames = chunk.len()?;
+                let mut translation = [0; 3];
+                let mut rotation = IDENTITY;
+                if frames > 0 {
+                    let frame = chunk.dict()?;
+                    if let Some(value) = frame.get("_t") {
+                        for (axis, component) in value.split_whitespace().take(3).enumerate() {
+                            translation[axis] = component
+                                .parse()
+                                .map_err(|_| invalid_data(format!("invalid translation {:?}", value)))?;
+                        }
+                    }
+                    if let Some(value) = frame.get("_r") {
+                        let bits = value
+                            .parse()
+                            .map_err(|_| invalid_data(format!("invalid rotation {:?}", value)))?;
+                        rotation = decode_rotation(bits)
+                            .ok_or_else(|| invalid_data(format!("invalid rotation {:?}", value)))?;
+                    }
+                }
+                scene.insert(id, SceneNode::Transform { child, translation, rotation });
+            }
+            b"nGRP" => {
+                let id = chunk.i32()?;
+                chunk.dict()?;
+                let count = chunk.len()?;
+                let children = (0..count).map(|_| chunk.i32()).collect::<io::Result<_>>()?;
+                scene.insert(id, SceneNode::Group { children });
+            }
+            b"nSHP" => {
+                let id = chunk.i32()?;
+                chunk.dict()?;
+                let count = chunk.len()?;
+                let mut shape_models = Vec::with_capacity(count);
+                for _ in 0..count {
+                    shape_models.push(chunk.i32()?);
+                    chunk.dict()?;
+                }
+                scene.insert(id, SceneNode::Shape { models: shape_models });
+            }
+            _ => {}
+        }
+    }
+
+    let mut voxels = vec![];
+    if scene.is_empty() {
+        // files without a scene graph have all models at the origin
+        for model in &models {
+            voxels.extend_from_slice(&model.voxels);
+        }
+    } else {
+        place_scene_node(&scene, &models, 0, [0; 3], IDENTITY, &mut voxels, 0)?;
+    }
+
+    let octree = build_octree(&voxels, size)?;
+    Ok((octree, palette.unwrap_or_else(default_palette)))
+}
+
+// `_r` stores the rotation matrix as the column of the non zero entry of the first two rows
+// (two bits each) followed by the signs of the three rows.
+fn decode_rotation(bits: u8) -> Option<[[i32; 3]; 3]> {
+    let first = (bits & 3) as usize;
+    let second = ((bits >> 2) & 3) as usize;
+    if first > 2 || second > 2 || first == second {
+        return None;
+    }
+    let third = 3 - first - second;
+    let mut rotation = [[0; 3]; 3];
+    for (row, column) in [first, second, third].iter().enumerate() {
+        rotation[row][*column] = if bits & (1 << (4 + row)) == 0 { 1 } else { -1 };
+    }
+    Some(rotation)
+}
+
+// `rotation` times `v` plus `translation`. Nested transforms can add up to more than an i32
+// holds, which makes the file invalid.
+fn transform(rotation: &[[i32; 3]; 3], v: [i32; 3], translation: [i32; 3]) -> io::Result<[i32; 3]> {
+    let mut result = [0; 3];
+    for (row, value) in result.iter_mut().enumerate() {
+        *value = (0..3)
+            .try_fold(translation[row], |sum, column| rotation[row][column].checked_mul(v[column])?.checked_add(sum))
+            .ok_or_else(|| invalid_data(format!("{:?} translated by {:?} is out of range", v, translation)))?;
+    }
+    Ok(result)
+}
+
+fn multiply(a: &[[i32; 3]; 3], b: &[[i32; 3]; 3]) -> [[i32; 3]; 3] {
+    let mut result = [[0; 3]; 3];
+    for row in 0..3 {
+        for column in 0..3 {
+            result[row][column] = (0..3).map(|i| a[row][i] * b[i][column]).sum();
+        }
+    }
+    result
+}
+
+fn place_scene_node(
+    scene: &HashMap<i32, SceneNode>,
+    models: &[Model],
+    id: i32,
+    translation: [i32; 3],
+    rotation: [[i32; 3]; 3],
+    voxels: &mut Vec<([i32; 3], u8)>,
+    depth: usize,
+) -> io::Result<()> {
+    if depth > scene.len() {
+        return Err(invalid_data("cycle in the scene graph".to_string()));
+    }
+    match scene.get(&id) {
+        Some(SceneNode::Transform { child, translation: local_translation, rotation: local_rotation }) => {
+            let translation = transform(&rotation, *local_translation, translation)?;
+            let rotation = multiply(&rotation, local_rotation);
+            place_scene_node(scene, models, *child, translation, rotation, voxels, depth + 1)
+        }
+        Some(SceneNode::Group { children }) => {
+            for child in children {
+                place_scene_node(scene, models, *child, translation, rotation, voxels, depth + 1)?;
+            }
+            Ok(())
+        }
+        Some(SceneNode::Shape { models: shape_models }) => {
+            for model_id in shape_models {
+                let model = models
+                    .get(*model_id as usize)
+                    .ok_or_else(|| invalid_data(format!("shape references missing model {}", model_id)))?;
+                // models are centered on their translation
+                let center = [model.size[0] / 2, model.size[1] / 2, model.size[2] / 2];
+                for (position, color) in &model.voxels {
+                    let local = [position[0] - center[0], position[1] - center[1], position[2] - center[2]];
+                    voxels.push((transform(&rotation, local, translation)?, *color));
+                }
+            }
+            Ok(())
+        }
+        None => Err(invalid_data(format!("scene graph references missing node {}", id))),
+    }
+}
+
+// Files without voxels give a tree that is a single empty leaf.
+fn build_octree(voxels: &[([i32; 3], u8)], size: f32) -> io::Result<Octree> {
+    if voxels.is_empty() {
+        let mut octree = Octree::new(0, size).map_err(|err| invalid_data(err.to_string()))?;
+        Octree::generate_ropes(&mut octree.data);
+        return Ok(octree);
+    }
+    let mut min = [i32::MAX; 3];
+    let mut max = [i32::MIN; 3];
+    for (position, _) in voxels {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(position[axis]);
+            max[axis] = max[axis].max(position[axis]);
+        }
+    }
+    // models can be translated far apart, too far for the tree is an error from `Octree::new`
+    let extent = (0..3).map(|axis| max[axis] as i64 - min[axis] as i64 + 1).max().unwrap();
+    let mut depth = 0;
+    while 1i64 << depth < extent {
+        depth += 1;
+    }
+
+    let mut octree = Octree::new(depth, size).map_err(|err| invalid_data(err.to_string()))?;
+    for (position, color) in voxels {
+        if *color != 0 {
+            octree
+                .set_voxel(position[0] - min[0], position[1] - min[1], position[2] - min[2], depth, *color as i32)
+                .map_err(|err| invalid_data(err.to_string()))?;
+        }
+    }
+    Octree::generate_ropes(&mut octree.data);
+    Ok(octree)
+}
+
+// The palette MagicaVoxel uses for files without an RGBA chunk: a 6x6x6 color cube without
+// black followed by red, green, blue and gray ramps.
+fn default_palette() -> Vec<[u8; 4]> {
+    let steps = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    let ramp = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+    let mut palette = vec![[0; 4]];
+    for r in steps {
+        for g in steps {
+            for b in steps {
+                if r | g | b != 0 {
+                    palette.push([r, g, b, 0xff]);
+                }
+            }
+        }
+    }
+    palette.extend(ramp.iter().map(|&v| [v, 0, 0, 0xff]));
+    palette.extend(ramp.iter().map(|&v| [0, v, 0, 0xff]));
+    palette.extend(ramp.iter().map(|&v| [0, 0, v, 0xff]));
+    palette.extend(ramp.iter().map(|&v| [v, v, v, 0xff]));
+    palette
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io;
+
+    use super::parse_vox;
+    use crate::octree::Octree;
+
+    fn chunk(id: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&(content.len() as i32).to_le_bytes());
+        bytes.extend_from_slice(&0i32.to_le_bytes());
+        bytes.extend_from_slice(content);
+        bytes
+    }
+
+    fn file(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let children = chunks.concat();
+        let mut bytes = b"VOX ".to_vec();
+        bytes.extend_from_slice(&150i32.to_le_bytes());
+        bytes.extend_from_slice(b"MAIN");
+        bytes.extend_from_slice(&0i32.to_le_bytes());
+        bytes.extend_from_slice(&(children.len() as i32).to_le_bytes());
+        bytes.extend(children);
+        bytes
+    }
+
+    fn ints(values: &[i32]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_le_bytes()).collect()
+    }
+
+    fn dict(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut bytes = ints(&[entries.len() as i32]);
+        for (key, value) in entries {
+            for string in [key, value] {
+                bytes.extend(ints(&[string.len() as i32]));
+                bytes.extend_from_slice(string.as_bytes());
+            }
+        }
+        bytes
+    }
+
+    fn model(size: [i32; 3], voxels: &[[u8; 4]]) -> Vec<Vec<u8>> {
+        vec![
+            chunk(b"SIZE", &ints(&size)),
+            chunk(b"XYZI", &[ints(&[voxels.len() as i32]), voxels.concat()].concat()),
+        ]
+    }
+
+    fn transform(id: i32, child: i32, translation: &str) -> Vec<u8> {
+        let content = [ints(&[id]), dict(&[]), ints(&[child, -1, 0, 1]), dict(&[("_t", translation)])].concat();
+        chunk(b"nTRN", &content)
+    }
+
+    // Material of every non empty voxel at the depth of the tree
+    fn voxels(octree: &Octree) -> HashMap<[i32; 3], i32> {
+        let mut voxels = HashMap::new();
+        for leaf in octree.leaves() {
+            let extent = 1 << (octree.depth - leaf.level);
+            for i in 0..extent * extent * extent {
+                let offset = [i % extent, i / extent % extent, i / (extent * extent)];
+                voxels.insert([0, 1, 2].map(|axis| leaf.coords[axis] * extent + offset[axis]), leaf.material_id);
+            }
+        }
+        voxels
+    }
+
+    #[test]
+    fn loads_a_single_model() {
+        let bytes = file(&model([4, 4, 4], &[[0, 0, 0, 3], [3, 1, 2, 7], [1, 1, 1, 0]]));
+        let (octree, palette) = parse_vox(&bytes, 8.0).unwrap();
+        assert_eq!(octree.validate(), Ok(()));
+        assert_eq!(octree.depth, 2);
+        assert_eq!(voxels(&octree), HashMap::from([([0, 0, 0], 3), ([3, 1, 2], 7)]));
+        // the default palette
+        assert_eq!(palette.len(), 256);
+        assert_eq!(palette[1], [0xff, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn places_models_with_their_transforms() {
+        let mut chunks = model([2, 2, 2], &[[0, 0, 0, 1], [1, 1, 1, 1]]);
+        chunks.extend(model([2, 2, 2], &[[0, 0, 0, 2]]));
+        chunks.extend([
+            transform(0, 1, "0 0 0"),
+            chunk(b"nGRP", &[ints(&[1]), dict(&[]), ints(&[2, 2, 4])].concat()),
+            transform(2, 3, "0 0 0"),
+            chunk(b"nSHP", &[ints(&[3]), dict(&[]), ints(&[1, 0]), dict(&[])].concat()),
+            transform(4, 5, "4 0 -2"),
+            chunk(b"nSHP", &[ints(&[5]), dict(&[]), ints(&[1, 1]), dict(&[])].concat()),
+        ]);
+        let (octree, _) = parse_vox(&file(&chunks), 8.0).unwrap();
+        assert_eq!(octree.validate(), Ok(()));
+        // the models are centered on their translations, (-1, -1, -3) ends up at the origin
+        assert_eq!(octree.depth, 3);
+        assert_eq!(voxels(&octree), HashMap::from([([0, 0, 2], 1), ([1, 1, 3], 1), ([4, 0, 0], 2)]));
+    }
+
+    #[test]
+    fn maps_the_rgba_palette() {
+        let colors = (0..256).flat_map(|i| [i as u8, 255 - i as u8, 7, 255]).collect::<Vec<u8>>();
+        let mut chunks = model([1, 1, 1], &[[0, 0, 0, 255]]);
+        chunks.push(chunk(b"RGBA", &colors));
+        let (octree, palette) = parse_vox(&file(&chunks), 8.0).unwrap();
+        assert_eq!(palette.len(), 256);
+        assert_eq!(palette[0], [0; 4]);
+        // palette index i is color i - 1 of the chunk
+        assert_eq!(palette[1], [0, 255, 7, 255]);
+        assert_eq!(palette[255], [254, 1, 7, 255]);
+        assert_eq!(voxels(&octree), HashMap::from([([0, 0, 0], 255)]));
+    }
+
+    #[test]
+    fn loads_an_empty_model() {
+        let (octree, _) = parse_vox(&file(&model([4, 4, 4], &[])), 8.0).unwrap();
+        assert_eq!(octree.validate(), Ok(()));
+        assert_eq!((octree.depth, octree.data.len()), (0, 1));
+        assert!(octree.data[0].is_leaf());
+        assert_eq!(octree.data[0].material_id, Octree::EMPTY);
+    }
+
+    #[test]
+    fn rejects_models_too_far_apart() {
+        let mut chunks = model([1, 1, 1], &[[0, 0, 0, 1]]);
+        chunks.extend([
+            transform(0, 1, "0 0 0"),
+            chunk(b"nGRP", &[ints(&[1]), dict(&[]), ints(&[2, 2, 4])].concat()),
+            transform(2, 3, "-2000000000 0 0"),
+            chunk(b"nSHP", &[ints(&[3]), dict(&[]), ints(&[1, 0]), dict(&[])].concat()),
+            transform(4, 3, "2000000000 0 0"),
+        ]);
+        assert!(parse_vox(&file(&chunks), 8.0).is_err());
+    }
+    #[test]
+    fn rejects_translations_out_of_range() {
+        // two nested transforms adding up to more than i32::MAX
+        let mut chunks = model([1, 1, 1], &[[0, 0, 0, 1]]);
+        chunks.extend([
+            transform(0, 1, "2000000000 0 0"),
+            transform(1, 2, "0 0 0"),
+            transform(2, 3, "2000000000 0 0"),
+            chunk(b"nSHP", &[ints(&[3]), dict(&[]), ints(&[1, 0]), dict(&[])].concat()),
+        ]);
+        let err = parse_vox(&file(&chunks), 8.0).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // a voxel past the end of the range
+        let mut chunks = model([1, 1, 1], &[[1, 0, 0, 1]]);
+        chunks.extend([
+            transform(0, 1, "2147483647 0 0"),
+            chunk(b"nSHP", &[ints(&[1]), dict(&[]), ints(&[1, 0]), dict(&[])].concat()),
+        ]);
+        let err = parse_vox(&file(&chunks), 8.0).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
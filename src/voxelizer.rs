@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Vector4};
+
+use crate::octree::{Node, Octree};
+
+pub struct Mesh {
+    pub triangles: Vec<[Vector3<f32>; 3]>,
+}
+
+fn invalid_data<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+// meshes are Y up, the tracer uses Z up
+fn to_z_up(v: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(v.x, -v.z, v.y)
+}
+
+impl Mesh {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("obj") => Self::load_obj(path),
+            Some("gltf") | Some("glb") => Self::load_gltf(path),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported mesh format {}", path.display()),
+            )),
+        }
+    }
+
+    pub fn load_obj<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let options = tobj::LoadOptions { triangulate: true, ..Default::default() };
+        let (models, _) = tobj::load_obj(path.as_ref(), &options).map_err(invalid_data)?;
+        let mut triangles = vec![];
+        for model in &models {
+            let positions = &model.mesh.positions;
+            let vertex = |index: u32| {
+                let i = index as usize * 3;
+                to_z_up(Vector3::new(positions[i], positions[i + 1], positions[i + 2]))
+            };
+            for face in model.mesh.indices.chunks_exact(3) {
+                triangles.push([vertex(face[0]), vertex(face[1]), vertex(face[2])]);
+            }
+        }
+        Ok(Self { triangles })
+    }
+
+    pub fn load_gltf<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let (document, buffers, _) = gltf::import(path).map_err(invalid_data)?;
+        let mut triangles = vec![];
+        if let Some(scene) = document.default_scene().or_else(|| document.scenes().next()) {
+            for node in scene.nodes() {
+                Self::add_gltf_node(&node, Matrix4::identity(), &buffers, &mut triangles);
+            }
+        }
+        Ok(Self { triangles })
+    }
+
+    fn add_gltf_node(
+        node: &gltf::Node,
+        parent: Matrix4<f32>,
+        buffers: &[gltf::buffer::Data],
+        triangles: &mut Vec<[Vector3<f32>; 3]>,
+    ) {
+        let transform = parent * Matrix4::from(node.transform().matrix());
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    continue;
+                }
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+                let positions: Vec<Vector3<f32>> = match reader.read_positions() {
+                    Some(positions) => positions
+                        .map(|p| to_z_up((transform * Vector4::new(p[0], p[1], p[2], 1.0)).truncate()))
+                        .collect(),
+                    None => continue,
+                };
+                let indices: Vec<u32> = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect(),
+                    None => (0..positions.len() as u32).collect(),
+                };
+                for face in indices.chunks_exact(3) {
+                    triangles.push([
+                        positions[face[0] as usize],
+                        positions[face[1] as usize],
+                        positions[face[2] as usize],
+                    ]);
+                }
+            }
+        }
+        for child in node.children() {
+            Self::add_gltf_node(&child, transform, buffers, triangles);
+        }
+    }
+}
+
+// Scales the mesh uniformly so it fits the 2^depth voxel grid of an octree with half extent
+// `size`, then marks every voxel a triangle touches. With `solid` the inside of the mesh is
+// filled as well, which only gives sensible results for closed meshes.
+pub fn voxelize(mesh: &Mesh, depth: i32, size: f32, solid: bool) -> Result<Octree, String> {
+    Octree::check_depth(depth).map_err(|err| err.to_string())?;
+    let n = (1 << depth) as f32;
+    let mut min = Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+    let mut max = -min;
+    for triangle in &mesh.triangles {
+        for vertex in triangle {
+            if !(vertex.x.is_finite() && vertex.y.is_finite() && vertex.z.is_finite()) {
+                return Err(format!("mesh has a vertex at {:?}", vertex));
+            }
+            for axis in 0..3 {
+                min[axis] = min[axis].min(vertex[axis]);
+                max[axis] = max[axis].max(vertex[axis]);
+            }
+        }
+    }
+    let extent = max - min;
+    let largest = extent.x.max(extent.y).max(extent.z);
+    let scale = if largest > 0.0 { n / largest } else { 1.0 };
+    let center = (min + max) * 0.5;
+    let offset = Vector3::new(n / 2.0, n / 2.0, n / 2.0);
+    let triangles: Vec<[Vector3<f32>; 3]> = mesh
+        .triangles
+        .iter()
+        .map(|triangle| triangle.map(|vertex| (vertex - center) * scale + offset))
+        .collect();
+
+    let mut data = vec![];
+    let candidates = (0..triangles.len()).collect::<Vec<_>>();
+    if candidates.is_empty() {
+        data.push(Node::default());
+    } else {
+        voxelize_internal(&triangles, &candidates, 0, [0; 3], depth, &mut data);
+    }
+    let mut octree = Octree { data, depth, size, free: vec![] };
+    if solid {
+        fill_interior(&mut octree, &triangles);
+    }
+    octree.optimize();
+    Ok(octree)
+}
+
+fn voxelize_internal(
+    triangles: &[[Vector3<f32>; 3]],
+    candidates: &[usize],
+    level: i32,
+    coords: [i32; 3],
+    depth: i32,
+    data: &mut Vec<Node>,
+) -> i32 {
+    let index = data.len();
+    data.push(Node { level, ..Default::default() });
+    if level == depth {
+        data[index].material_id = Octree::SOLID;
+        return index as i32;
+    }
+    // cells are measured in voxels of the finest level
+    let half = (1 << (depth - level - 1)) as f32 / 2.0;
+    for i in 0..8 {
+        let child_coords = [coords[0] * 2 + (i & 1), coords[1] * 2 + ((i >> 1) & 1), coords[2] * 2 + ((i >> 2) & 1)];
+        let center = Vector3::new(
+            (2 * child_coords[0] + 1) as f32 * half,
+            (2 * child_coords[1] + 1) as f32 * half,
+            (2 * child_coords[2] + 1) as f32 * half,
+        );
+        let overlapping = candidates
+            .iter()
+            .copied()
+            .filter(|&triangle| triangle_box_overlap(center, half, &triangles[triangle]))
+            .collect::<Vec<_>>();
+        if !overlapping.is_empty() {
+            let sub_voxel = voxelize_internal(triangles, &overlapping, level + 1, child_coords, depth, data);
+            data[index].sub_voxels[i as usize] = sub_voxel;
+        }
+    }
+    index as i32
+}
+
+// Separating axis test between a triangle and an axis aligned cube (Akenine-Möller).
+fn triangle_box_overlap(center: Vector3<f32>, half: f32, triangle: &[Vector3<f32>; 3]) -> bool {
+    let v = triangle.map(|vertex| vertex - center);
+    let projected_outside = |axis: Vector3<f32>| {
+        let p = [axis.dot(v[0]), axis.dot(v[1]), axis.dot(v[2])];
+        let r = half * (axis.x.abs() + axis.y.abs() + axis.z.abs());
+        p[0].min(p[1]).min(p[2]) > r || p[0].max(p[1]).max(p[2]) < -r
+    };
+
+    let unit = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()];
+    if unit.iter().any(|&axis| projected_outside(axis)) {
+        return false;
+    }
+    let edges = [v[1] - v[0], v[2] - v[1], v[0] - v[2]];
+    if projected_outside(edges[0].cross(edges[1])) {
+        return false;
+    }
+    !edges.iter().any(|&edge| unit.iter().any(|&axis| projected_outside(axis.cross(edge))))
+}
+
+// Casts a ray along z through the center of every voxel column and fills the voxels between
+// pairs of crossings with the mesh surface.
+fn fill_interior(octree: &mut Octree, triangles: &[[Vector3<f32>; 3]]) {
+    let n = 1 << octree.depth;
+    let edge = |a: Vector3<f32>, b: Vector3<f32>, x: f32, y: f32| (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x);
+    // Points exactly on an edge only count for the triangle where it is a top or left edge, so
+    // columns through edges shared by two triangles see a single crossing.
+    let covers = |weight: f32, a: Vector3<f32>, b: Vector3<f32>| {
+        weight > 0.0 || (weight == 0.0 && (b.y < a.y || (b.y == a.y && b.x < a.x)))
+    };
+    let mut columns: HashMap<(i32, i32), Vec<f32>> = HashMap::new();
+    for &[a, b, c] in triangles {
+        let area = edge(a, b, c.x, c.y);
+        if area == 0.0 {
+            continue;
+        }
+        // counter clockwise, so the inside is left of every edge
+        let (b, c, area) = if area < 0.0 { (c, b, -area) } else { (b, c, area) };
+        let first = |min: f32| ((min - 0.5).ceil() as i32).max(0);
+        let last = |max: f32| ((max - 0.5).floor() as i32).min(n - 1);
+        for i in first(a.x.min(b.x).min(c.x))..=last(a.x.max(b.x).max(c.x)) {
+            for j in first(a.y.min(b.y).min(c.y))..=last(a.y.max(b.y).max(c.y)) {
+                let (x, y) = (i as f32 + 0.5, j as f32 + 0.5);
+                let weights = [edge(b, c, x, y), edge(c, a, x, y), edge(a, b, x, y)];
+                if covers(weights[0], b, c) && covers(weights[1], c, a) && covers(weights[2], a, b) {
+                    let z = (weights[0] * a.z + weights[1] * b.z + weights[2] * c.z) / area;
+                    columns.entry((i, j)).or_default().push(z);
+                }
+            }
+        }
+    }
+
+    let depth = octree.depth;
+    for ((i, j), mut crossings) in columns {
+        crossings.sort_by(f32::total_cmp);
+        for pair in crossings.chunks_exact(2) {
+            let start = ((pair[0] - 0.5).ceil() as i32).max(0);
+            let end = ((pair[1] - 0.5).floor() as i32).min(n - 1);
+            for k in start..=end {
+                // the columns and `k` are clamped to the grid
+                octree.set_voxel(i, j, k, depth, Octree::SOLID).expect("voxel outside of the grid");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use super::{triangle_box_overlap, voxelize, Mesh};
+    use crate::octree::tests::assert_voxels;
+    use crate::octree::Octree;
+
+    // A closed box from `min` to `max` as 12 triangles facing outwards.
+    fn box_mesh(min: Vector3<f32>, max: Vector3<f32>) -> Mesh {
+        let corner = |i: usize| {
+            Vector3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+        let faces = [[0, 4, 6, 2], [1, 3, 7, 5], [0, 1, 5, 4], [2, 6, 7, 3], [0, 2, 3, 1], [4, 5, 7, 6]];
+        let triangles = faces
+            .iter()
+            .flat_map(|&[a, b, c, d]| [[corner(a), corner(b), corner(c)], [corner(a), corner(c), corner(d)]])
+            .collect();
+        Mesh { triangles }
+    }
+
+    #[test]
+    fn triangles_overlap_boxes() {
+        let center = Vector3::new(0.0, 0.0, 0.0);
+        let through = [Vector3::new(-2.0, -2.0, 0.0), Vector3::new(2.0, -2.0, 0.0), Vector3::new(0.0, 2.0, 0.0)];
+        assert!(triangle_box_overlap(center, 1.0, &through));
+        let touching = through.map(|vertex| vertex + Vector3::new(0.0, 0.0, 1.0));
+        assert!(triangle_box_overlap(center, 1.0, &touching));
+        let above = through.map(|vertex| vertex + Vector3::new(0.0, 0.0, 1.5));
+        assert!(!triangle_box_overlap(center, 1.0, &above));
+        // the bounds overlap the box, only the plane of the triangle separates them
+        let tilted = [Vector3::new(1.5, 0.0, 0.0), Vector3::new(0.0, 1.5, 0.0), Vector3::new(0.0, 0.0, 1.5)];
+        assert!(triangle_box_overlap(center, 1.0, &tilted));
+        let tilted = tilted.map(|vertex| vertex * 2.5);
+        assert!(!triangle_box_overlap(center, 1.0, &tilted));
+        // only the cross product of an edge and an axis separates them
+        let corner = [Vector3::new(1.5, 0.8, 0.0), Vector3::new(0.8, 1.5, 0.0), Vector3::new(3.0, 3.0, 0.0)];
+        assert!(!triangle_box_overlap(center, 1.0, &corner));
+    }
+
+    #[test]
+    fn marks_the_voxels_touching_the_surface() {
+        // scaled to 0..8 along x and 2..6 along y and z
+        let mesh = box_mesh(Vector3::new(-2.0, -1.0, -1.0), Vector3::new(2.0, 1.0, 1.0));
+        let octree = voxelize(&mesh, 3, 8.0, false).unwrap();
+        assert_eq!(octree.validate(), Ok(()));
+        let within = |coords: [i32; 3], min: [i32; 3], max: [i32; 3]| {
+            (0..3).all(|axis| (min[axis]..=max[axis]).contains(&coords[axis]))
+        };
+        // the voxels touching the box, except for the ones between its faces
+        assert_voxels(&octree, 3, |coords| {
+            if within(coords, [0, 1, 1], [7, 6, 6]) && !within(coords, [1, 3, 3], [6, 4, 4]) {
+                Octree::SOLID
+            } else {
+                Octree::EMPTY
+            }
+        });
+    }
+
+    #[test]
+    fn fills_the_inside_of_a_closed_mesh() {
+        let mesh = box_mesh(Vector3::new(-2.0, -1.0, -1.0), Vector3::new(2.0, 1.0, 1.0));
+        let octree = voxelize(&mesh, 3, 8.0, true).unwrap();
+        assert_eq!(octree.validate(), Ok(()));
+        assert_voxels(&octree, 3, |[_, y, z]| {
+            if (1..=6).contains(&y) && (1..=6).contains(&z) {
+                Octree::SOLID
+            } else {
+                Octree::EMPTY
+            }
+        });
+        // a cube fills the whole grid, which collapses into the root
+        let cube = voxelize(&box_mesh(Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0)), 4, 8.0, true);
+        assert_eq!(cube.unwrap().data.len(), 1);
+    }
+
+    #[test]
+    fn rejects_invalid_depths_and_vertices() {
+        let mesh = box_mesh(Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0));
+        assert!(voxelize(&mesh, -1, 8.0, false).is_err());
+        assert!(voxelize(&mesh, 31, 8.0, false).is_err());
+        let mut broken = box_mesh(Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0));
+        broken.triangles[3][1].z = f32::NAN;
+        assert!(voxelize(&broken, 3, 8.0, true).is_err());
+        broken.triangles[3][1].z = f32::INFINITY;
+        assert!(voxelize(&broken, 3, 8.0, true).is_err());
+    }
+}
@@ -0,0 +1,35 @@
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+
+// Watches the shader directory so the pipelines can be rebuilt as soon as a shader is saved.
+pub struct ShaderWatcher {
+    // stops watching when dropped
+    _watcher: RecommendedWatcher,
+    events: Receiver<DebouncedEvent>,
+}
+
+impl ShaderWatcher {
+    pub fn new(dir: &Path) -> notify::Result<Self> {
+        let (sender, events) = channel();
+        // editors often write a file in several steps, they are reported as one change
+        let mut watcher: RecommendedWatcher = Watcher::new(sender, Duration::from_millis(200))?;
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        Ok(Self { _watcher: watcher, events })
+    }
+
+    // Whether a shader was written, created or renamed since the last call, never blocks.
+    pub fn changed(&self) -> bool {
+        let mut changed = false;
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                DebouncedEvent::Write(_) | DebouncedEvent::Create(_) | DebouncedEvent::Rename(..) => changed = true,
+                DebouncedEvent::Error(err, path) => eprintln!("failed to watch {:?}: {}", path, err),
+                _ => {}
+            }
+        }
+        changed
+    }
+}